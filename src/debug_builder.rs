@@ -0,0 +1,169 @@
+use std::borrow::{Borrow, BorrowMut};
+
+use hashbrown::HashMap;
+use p3_air::{Air, AirBuilder, AirBuilderWithPublicValues, BaseAir, PairBuilder};
+use p3_baby_bear::BabyBear;
+use p3_field::AbstractField;
+use p3_matrix::dense::RowMajorMatrix;
+use p3_matrix::Matrix;
+use sp1_stark::air::{AirInteraction, InteractionScope, MachineAir, MessageBuilder};
+use sp1_stark::InteractionKind;
+
+use crate::machine::{ProgExecRecord, RandomXAir};
+use crate::math_ops::I64MathOp;
+use crate::prog_exec::{generate_program_trace, generate_public_values, ProgExec};
+use crate::register::{init_regs, RegFile};
+use crate::stark_primitives::{ProgExecCols, BIN_OP_ROW_SIZE};
+use crate::Cli;
+
+// Evaluates the constraints of a chip on a window of two rows and records the
+// interactions instead of proving them. The tests count the failed constraints of
+// tampered traces.
+pub struct DebugBuilder {
+    main: RowMajorMatrix<BabyBear>,
+    preprocessed: RowMajorMatrix<BabyBear>,
+    public_values: Vec<BabyBear>,
+    is_first_row: bool,
+    is_last_row: bool,
+    failures: usize,
+    interactions: Vec<(bool, AirInteraction<BabyBear>)>,
+}
+
+impl AirBuilder for DebugBuilder {
+    type F = BabyBear;
+    type Expr = BabyBear;
+    type Var = BabyBear;
+    type M = RowMajorMatrix<BabyBear>;
+
+    fn main(&self) -> Self::M {
+        self.main.clone()
+    }
+
+    fn is_first_row(&self) -> Self::Expr {
+        BabyBear::from_bool(self.is_first_row)
+    }
+
+    fn is_last_row(&self) -> Self::Expr {
+        BabyBear::from_bool(self.is_last_row)
+    }
+
+    fn is_transition_window(&self, size: usize) -> Self::Expr {
+        assert_eq!(size, 2, "only two row windows are supported");
+        BabyBear::from_bool(!self.is_last_row)
+    }
+
+    fn assert_zero<I: Into<Self::Expr>>(&mut self, x: I) {
+        if x.into() != BabyBear::zero() {
+            self.failures += 1;
+        }
+    }
+}
+
+impl AirBuilderWithPublicValues for DebugBuilder {
+    type PublicVar = BabyBear;
+
+    fn public_values(&self) -> &[Self::PublicVar] {
+        &self.public_values
+    }
+}
+
+impl PairBuilder for DebugBuilder {
+    fn preprocessed(&self) -> Self::M {
+        self.preprocessed.clone()
+    }
+}
+
+impl MessageBuilder<AirInteraction<BabyBear>> for DebugBuilder {
+    fn send(&mut self, message: AirInteraction<BabyBear>, _scope: InteractionScope) {
+        self.interactions.push((true, message));
+    }
+
+    fn receive(&mut self, message: AirInteraction<BabyBear>, _scope: InteractionScope) {
+        self.interactions.push((false, message));
+    }
+}
+
+// the row r and the next one, the last row wraps to the first
+fn window(trace: &RowMajorMatrix<BabyBear>, r: usize) -> RowMajorMatrix<BabyBear> {
+    let mut values = trace.row_slice(r).to_vec();
+    values.extend_from_slice(&trace.row_slice((r + 1) % trace.height()));
+    RowMajorMatrix::new(values, trace.width())
+}
+
+// The number of failed constraints of every chip over the program trace, plus the
+// number of unbalanced interactions. A valid trace has none.
+pub fn count_failures(
+    prog: &ProgExec<BabyBear>,
+    trace: &RowMajorMatrix<BabyBear>,
+    public_values: &[BabyBear],
+) -> usize {
+    let record = ProgExecRecord::new(prog, trace.clone(), public_values.to_vec());
+    let mut failures = 0;
+    let mut balance: HashMap<(InteractionKind, Vec<BabyBear>), BabyBear> = HashMap::new();
+    for air in RandomXAir::get_all() {
+        let main = air.generate_trace(&record, &mut ProgExecRecord::default());
+        assert_eq!(main.width(), BaseAir::<BabyBear>::width(&air));
+        let preprocessed = air.generate_preprocessed_trace(prog);
+        let rows = main.height();
+        for r in 0..rows {
+            let mut builder = DebugBuilder {
+                main: window(&main, r),
+                preprocessed: preprocessed
+                    .as_ref()
+                    .map_or(RowMajorMatrix::new(vec![], 1), |p| window(p, r)),
+                public_values: public_values.to_vec(),
+                is_first_row: r == 0,
+                is_last_row: r == rows - 1,
+                failures: 0,
+                interactions: vec![],
+            };
+            air.eval(&mut builder);
+            failures += builder.failures;
+            for (is_send, message) in builder.interactions {
+                let count = balance
+                    .entry((message.kind, message.values))
+                    .or_insert(BabyBear::zero());
+                if is_send {
+                    *count += message.multiplicity;
+                } else {
+                    *count -= message.multiplicity;
+                }
+            }
+        }
+    }
+    failures + balance.values().filter(|&&c| c != BabyBear::zero()).count()
+}
+
+// The program run of the ops from the register file, its trace and public values
+pub fn run_program(
+    ops: Vec<I64MathOp<BabyBear>>,
+    regs: RegFile,
+    repetitions: u16,
+) -> (ProgExec<BabyBear>, RowMajorMatrix<BabyBear>, Vec<BabyBear>) {
+    let prog = ProgExec::new(ops, regs, [1; 32], [2; 32], [3; 32]);
+    let cli = Cli {
+        programs: 1,
+        repetitions,
+        recursive: false,
+    };
+    let (trace, final_regs) = generate_program_trace(&prog, &cli);
+    let public_values = generate_public_values(&prog, &final_regs, &trace);
+    (prog, trace, public_values)
+}
+
+// a register file with the operands r0 and r1 in the first registers
+pub fn test_regs(r0: i64, r1: i64) -> RegFile {
+    let mut regs = init_regs(8);
+    regs.int_regs[0] = r0;
+    regs.int_regs[1] = r1;
+    regs
+}
+
+pub fn row(trace: &RowMajorMatrix<BabyBear>, r: usize) -> &ProgExecCols<BabyBear> {
+    trace.values[r * BIN_OP_ROW_SIZE..(r + 1) * BIN_OP_ROW_SIZE].borrow()
+}
+
+// the columns of the row r, to tamper with
+pub fn row_mut(trace: &mut RowMajorMatrix<BabyBear>, r: usize) -> &mut ProgExecCols<BabyBear> {
+    trace.row_mut(r).borrow_mut()
+}
@@ -0,0 +1,1972 @@
+use std::borrow::BorrowMut;
+
+use p3_air::{AirBuilder, AirBuilderWithPublicValues};
+use p3_field::AbstractField;
+use p3_field::Field;
+use sp1_stark::air::{AirInteraction, InteractionScope, SP1AirBuilder};
+use sp1_stark::InteractionKind;
+
+use crate::{
+    math_ops::{populate_operands_trace_record, I64MathOps},
+    range_check::ByteOpcode,
+    scratchpad::pack_value,
+    stark_primitives::{FpLaneCols, FpRoundCols, ProgExecCols},
+};
+
+// RandomX FP registers never hold NaNs or denormals. The F group registers may become
+// zero or infinite through FAdd and FSub, the other FP ops constrain both the operands
+// and the result to be normal finite doubles.
+// Every op works on the pair of doubles, the lanes are proved independently.
+
+const SIGN_BIT: u64 = 1 << 63;
+const MANT_BITS: u64 = (1 << 52) - 1;
+// FSCAL_R flips the sign and the low 4 exponent bits
+pub const FSCAL_MASK: u64 = 0x80F0_0000_0000_0000;
+// An E group mask sets the exponent bits 8 and 9, its variable bits are the exponent
+// bits 4..8 and the low 22 mantissa bits. FDIV_M keeps the low 56 bits of the converted
+// divisor and ORs them with the mask.
+pub const E_MASK_EXP: u64 = 0x3000_0000_0000_0000;
+const E_MASK_VAR_BITS: u64 = 0x0F00_0000_003F_FFFF;
+const E_MASK_KEPT_BITS: u64 = (1 << 56) - 1;
+
+// the lane 0 operands are the low 64 bits, the lane 1 ones are the high 64 bits
+const LANES: usize = 2;
+
+// left, right and result limbs of the lane
+pub fn lane_operands<T>(local: &ProgExecCols<T>, lane: usize) -> (&[T; 8], &[T; 8], &[T; 8]) {
+    if lane == 0 {
+        (&local.left_arg, &local.right_arg, &local.result)
+    } else {
+        (&local.left_arg_hi, &local.right_arg_hi, &local.result_hi)
+    }
+}
+
+// asserts bits are the bits of the top 2 limbs of the double z
+pub fn eval_fp_bits<AB: AirBuilder + AirBuilderWithPublicValues>(
+    builder: &mut AB,
+    is_real: AB::Expr,
+    z: &[AB::Var; 8],
+    bits: &[AB::Var; 16],
+) {
+    let mut when_is_real = builder.when(is_real);
+
+    let mut limb_6 = AB::Expr::zero();
+    let mut limb_7 = AB::Expr::zero();
+    for j in 0..8 {
+        when_is_real.assert_bool(bits[j]);
+        when_is_real.assert_bool(bits[8 + j]);
+        limb_6 = limb_6 + bits[j] * AB::F::from_canonical_u32(1 << j);
+        limb_7 = limb_7 + bits[8 + j] * AB::F::from_canonical_u32(1 << j);
+    }
+    when_is_real.assert_eq(z[6], limb_6);
+    when_is_real.assert_eq(z[7], limb_7);
+}
+
+// Unpacks the double z into the sign, the biased exponent and the top 4 mantissa bits.
+// bits are the bits of the top 2 limbs and inv are the inverses of e and 2047 - e
+// so the double is normal and finite.
+pub fn eval_fp_unpack<AB: AirBuilder + AirBuilderWithPublicValues>(
+    builder: &mut AB,
+    is_real: AB::Expr,
+    z: &[AB::Var; 8],
+    bits: &[AB::Var; 16],
+    inv: &[AB::Var; 2],
+) -> (AB::Expr, AB::Expr, AB::Expr) {
+    eval_fp_bits(builder, is_real.clone(), z, bits);
+
+    let mut when_is_real = builder.when(is_real);
+
+    let mut mant_hi = AB::Expr::zero();
+    for j in 0..4 {
+        mant_hi = mant_hi + bits[j] * AB::F::from_canonical_u32(1 << j);
+    }
+    let mut exp = AB::Expr::zero();
+    for j in 4..15 {
+        exp = exp + bits[j] * AB::F::from_canonical_u32(1 << (j - 4));
+    }
+
+    when_is_real.assert_one(exp.clone() * inv[0]);
+    when_is_real.assert_one((AB::Expr::from_canonical_u32(2047) - exp.clone()) * inv[1]);
+
+    (bits[15].into(), exp, mant_hi)
+}
+
+// eval_fp_unpack of a double that is either normal, zero or infinite, the zero and inf
+// flags are set for the latter two. Their exponent is 0 or 2047 and the mantissa is zero.
+pub fn eval_fp_unpack_special<AB: AirBuilder + AirBuilderWithPublicValues>(
+    builder: &mut AB,
+    is_real: AB::Expr,
+    z: &[AB::Var; 8],
+    bits: &[AB::Var; 16],
+    inv: &[AB::Var; 2],
+    zero: AB::Var,
+    inf: AB::Var,
+) -> (AB::Expr, AB::Expr, AB::Expr) {
+    eval_fp_bits(builder, is_real.clone(), z, bits);
+
+    let mut when_is_real = builder.when(is_real);
+
+    let mut mant_hi = AB::Expr::zero();
+    for j in 0..4 {
+        mant_hi = mant_hi + bits[j] * AB::F::from_canonical_u32(1 << j);
+    }
+    let mut exp = AB::Expr::zero();
+    for j in 4..15 {
+        exp = exp + bits[j] * AB::F::from_canonical_u32(1 << (j - 4));
+    }
+
+    let max_exp = AB::Expr::from_canonical_u32(2047);
+    when_is_real.assert_bool(zero);
+    when_is_real.assert_bool(inf);
+    when_is_real.assert_zero(zero * exp.clone());
+    when_is_real.assert_zero(inf * (max_exp.clone() - exp.clone()));
+    when_is_real.assert_eq(exp.clone() * inv[0], AB::Expr::one() - zero);
+    when_is_real.assert_eq((max_exp - exp.clone()) * inv[1], AB::Expr::one() - inf);
+    let special = zero + inf;
+    for i in 0..6 {
+        when_is_real.assert_zero(special.clone() * z[i]);
+    }
+    when_is_real.assert_zero(special * mant_hi.clone());
+
+    (bits[15].into(), exp, mant_hi)
+}
+
+// asserts the sel columns are one-hot encoded and returns the encoded value
+pub fn eval_one_hot<AB: AirBuilder + AirBuilderWithPublicValues>(
+    builder: &mut AB,
+    is_real: AB::Expr,
+    sel: &[AB::Var],
+) -> AB::Expr {
+    let mut when_is_real = builder.when(is_real);
+
+    let mut sel_sum = AB::Expr::zero();
+    let mut value = AB::Expr::zero();
+    for (k, &s) in sel.iter().enumerate() {
+        when_is_real.assert_bool(s);
+        sel_sum = sel_sum + s;
+        value = value + s * AB::F::from_canonical_usize(k);
+    }
+    when_is_real.assert_one(sel_sum);
+
+    value
+}
+
+pub fn eval_fadd<AB: AirBuilder + AirBuilderWithPublicValues>(
+    builder: &mut AB,
+    local: &ProgExecCols<AB::Var>,
+    is_fadd: AB::Expr,
+    is_fsub: AB::Expr,
+) {
+    for lane in 0..LANES {
+        eval_fadd_lane(
+            builder,
+            local,
+            lane,
+            is_fadd.clone() + is_fsub.clone(),
+            is_fsub.clone(),
+        );
+    }
+}
+
+// IEEE-754 binary64 addition rounded in the fprc mode: to nearest with ties to even,
+// down, up or toward zero.
+// The operands are swapped into x and y so that |x| >= |y|. The y significand is
+// aligned as y * 2^(64 - shift), the sum x * 2^64 +- aligned y is exact for shifts
+// up to 63 and larger shifts only affect the sticky bit. The sum is normalized
+// with the leading bit at 124, so the 53-bit significand is in the limbs 9..16
+// and the guard bit is the top bit of the limb 8.
+// The left F group register may be zero or infinite, the right A group register is
+// always normal. A zero left gives the signed right and an infinite left is kept,
+// both exactly. Operands that cancel give a zero signed by the rounding mode and
+// a rounded result above the largest double overflows to either inf or the largest
+// double depending on the mode. Subnormal results are not supported, the A group
+// exponents keep RandomX sums far from them.
+pub fn eval_fadd_lane<AB: AirBuilder + AirBuilderWithPublicValues>(
+    builder: &mut AB,
+    local: &ProgExecCols<AB::Var>,
+    lane: usize,
+    is_real: AB::Expr,
+    is_sub: AB::Expr,
+) {
+    let (left, right, res) = lane_operands(local, lane);
+    let cols = local.fp_lane(lane);
+    let fadd = cols.fadd();
+
+    let (left_sign, left_exp, left_mant_hi) = eval_fp_unpack_special(
+        builder,
+        is_real.clone(),
+        left,
+        &cols.left_bits,
+        &cols.left_inv,
+        fadd.left_zero,
+        fadd.left_inf,
+    );
+    let (right_sign, right_exp, right_mant_hi) = eval_fp_unpack(
+        builder,
+        is_real.clone(),
+        right,
+        &cols.right_bits,
+        &cols.right_inv,
+    );
+    let (res_sign, res_exp, res_mant_hi) = eval_fp_unpack_special(
+        builder,
+        is_real.clone(),
+        res,
+        &cols.res_bits,
+        &cols.res_inv,
+        fadd.res_zero,
+        fadd.res_inf,
+    );
+    let align_q = eval_one_hot(builder, is_real.clone(), &fadd.align_q);
+    let align_r = eval_one_hot(builder, is_real.clone(), &fadd.align_r);
+    let norm_q = eval_one_hot(builder, is_real.clone(), &fadd.norm_q);
+    let norm_r = eval_one_hot(builder, is_real.clone(), &fadd.norm_r);
+
+    let base = AB::F::from_canonical_u32(256);
+    let one = AB::Expr::one();
+    let mut when_is_real = builder.when(is_real.clone());
+
+    // FSub flips the right sign, the magnitudes are subtracted if the signs differ
+    let sign = fadd.sign;
+    let op_sub = fadd.op_sub;
+    when_is_real.assert_eq(
+        sign,
+        right_sign.clone() + is_sub.clone() - right_sign.clone() * is_sub * AB::F::two(),
+    );
+    when_is_real.assert_eq(
+        op_sub,
+        left_sign.clone() + sign - left_sign.clone() * sign * AB::F::two(),
+    );
+
+    let mut prev_borrow = AB::Expr::zero();
+    for i in 0..8 {
+        let (left_mag, right_mag): (AB::Expr, AB::Expr) = if i < 7 {
+            (left[i].into(), right[i].into())
+        } else {
+            (
+                left[7] - left_sign.clone() * AB::F::from_canonical_u32(128),
+                right[7] - right_sign.clone() * AB::F::from_canonical_u32(128),
+            )
+        };
+        let borrow = fadd.borrow[i];
+        when_is_real.assert_bool(borrow);
+        when_is_real.assert_eq(
+            fadd.cmp[i],
+            left_mag - right_mag - prev_borrow + borrow * base,
+        );
+        prev_borrow = borrow.into();
+    }
+    let swap = fadd.borrow[7];
+
+    let x_exp = left_exp.clone() + swap * (right_exp.clone() - left_exp.clone());
+    when_is_real.assert_eq(
+        fadd.exp_diff,
+        (left_exp - right_exp) * (one.clone() - swap * AB::F::two()),
+    );
+
+    // 53-bit significands with the implicit leading bit
+    let mant = |z: &[AB::Var; 8], mant_hi: AB::Expr, i: usize| -> AB::Expr {
+        if i < 6 {
+            z[i].into()
+        } else {
+            mant_hi + AB::F::from_canonical_u32(16)
+        }
+    };
+    let mut x_mant = Vec::with_capacity(7);
+    for i in 0..7 {
+        let left_mant = mant(left, left_mant_hi.clone(), i);
+        let right_mant = mant(right, right_mant_hi.clone(), i);
+        x_mant.push(left_mant.clone() + swap * (right_mant.clone() - left_mant.clone()));
+        when_is_real.assert_eq(
+            fadd.mant_y[i],
+            right_mant.clone() + swap * (left_mant - right_mant),
+        );
+    }
+
+    let exp_diff = fadd.exp_diff;
+    let cap = fadd.cap;
+    let align = align_q * AB::F::from_canonical_u32(8) + align_r;
+    when_is_real.assert_bool(cap);
+    when_is_real.assert_zero((one.clone() - cap) * (exp_diff - align.clone()));
+    when_is_real.assert_zero(cap * (align - AB::F::from_canonical_u32(63)));
+    when_is_real.assert_zero(
+        cap * (exp_diff
+            - AB::F::from_canonical_u32(63)
+            - fadd.cap_diff[0]
+            - fadd.cap_diff[1] * base),
+    );
+
+    // y * 2^(64 - shift) = (y * 2^(8 - shift_r)) * 2^(8 * (7 - shift_q))
+    let mut pow = AB::Expr::zero();
+    for k in 0..8 {
+        pow = pow + fadd.align_r[k] * AB::F::from_canonical_u32(1 << (8 - k));
+    }
+    let mut shifted = vec![AB::Expr::zero(); 8];
+    for i in 0..7 {
+        let lo = fadd.align_lo[i];
+        let hi = fadd.align_hi[i];
+        when_is_real.assert_eq(fadd.mant_y[i] * pow.clone(), lo + hi * base);
+        shifted[i] = shifted[i].clone() + lo;
+        shifted[i + 1] = shifted[i + 1].clone() + hi;
+    }
+    for k in 0..16 {
+        let mut aligned = AB::Expr::zero();
+        for q in 0..8 {
+            if k + q >= 7 && k + q < 15 {
+                aligned = aligned + fadd.align_q[q] * shifted[k + q - 7].clone();
+            }
+        }
+        when_is_real.assert_eq(fadd.aligned[k], aligned);
+    }
+
+    // the carries are either 0 or the sign of the aligned y
+    let sigma = one.clone() - op_sub * AB::F::two();
+    let mut prev_carry = AB::Expr::zero();
+    for i in 0..16 {
+        let x = if (8..15).contains(&i) {
+            x_mant[i - 8].clone()
+        } else {
+            AB::Expr::zero()
+        };
+        let limb_sum = x + fadd.aligned[i] * sigma.clone() + prev_carry;
+        if i < 15 {
+            let carry = fadd.sum_carry[i];
+            when_is_real.assert_zero(carry * (carry - sigma.clone()));
+            when_is_real.assert_eq(limb_sum, fadd.sum[i] + carry * base);
+            prev_carry = carry.into();
+        } else {
+            when_is_real.assert_eq(limb_sum, fadd.sum[i]);
+            prev_carry = AB::Expr::zero();
+        }
+    }
+
+    // sum * 2^norm, no set bit is shifted out
+    let mut pow = AB::Expr::zero();
+    for k in 0..8 {
+        pow = pow + fadd.norm_r[k] * AB::F::from_canonical_u32(1 << k);
+    }
+    let mut shifted = vec![AB::Expr::zero(); 17];
+    for i in 0..16 {
+        let lo = fadd.norm_lo[i];
+        let hi = fadd.norm_hi[i];
+        when_is_real.assert_eq(fadd.sum[i] * pow.clone(), lo + hi * base);
+        shifted[i] = shifted[i].clone() + lo;
+        shifted[i + 1] = shifted[i + 1].clone() + hi;
+    }
+    let mut dropped = AB::Expr::zero();
+    for q in 0..16 {
+        for k in (16 - q)..17 {
+            dropped = dropped + fadd.norm_q[q] * shifted[k].clone();
+        }
+    }
+    when_is_real.assert_zero(dropped);
+    for k in 0..16 {
+        let mut norm = AB::Expr::zero();
+        for q in 0..=k {
+            norm = norm + fadd.norm_q[q] * shifted[k - q].clone();
+        }
+        when_is_real.assert_eq(fadd.norm[k], norm);
+    }
+    let norm_exp = norm_q * AB::F::from_canonical_u32(8) + norm_r;
+
+    let mut lower_sum = AB::Expr::zero();
+    for i in 0..8 {
+        lower_sum = lower_sum + fadd.norm[i];
+    }
+    // a cancelled sum is rounded as if its top limb was 16, the result is not taken from it
+    let cancel = fadd.cancel;
+    let mut sig: Vec<AB::Expr> = (9..16).map(|i| fadd.norm[i].into()).collect();
+    sig[6] = sig[6].clone() + cancel * AB::F::from_canonical_u32(16);
+    let guard_limb = fadd.norm[8].into();
+    let x_exp_norm = x_exp + AB::F::from_canonical_u32(8) - norm_exp;
+
+    // the magnitudes are equal, the sum is +0 but -0 when rounding down
+    let round_down = local.fprc_sel[1];
+    when_is_real.assert_bool(cancel);
+    for i in 0..16 {
+        when_is_real.assert_zero(cancel * fadd.sum[i]);
+    }
+    when_is_real.assert_zero(cancel * (one.clone() - fadd.res_zero));
+    when_is_real.assert_eq(
+        res_sign.clone(),
+        left_sign.clone() + swap * (sign - left_sign.clone()) + cancel * (round_down - left_sign),
+    );
+
+    // a zero left gives the signed right, an infinite left is the result
+    let left_zero = fadd.left_zero;
+    let left_inf = fadd.left_inf;
+    for i in 0..7 {
+        when_is_real.assert_zero(left_zero * (res[i] - right[i]));
+    }
+    when_is_real.assert_zero(
+        left_zero * (res[7] - right[7] - (sign - right_sign) * AB::F::from_canonical_u32(128)),
+    );
+    for i in 0..8 {
+        when_is_real.assert_zero(left_inf * (res[i] - left[i]));
+    }
+
+    let rounded = fadd.rounded;
+    when_is_real.assert_eq(rounded, one.clone() - left_zero - left_inf - cancel);
+    when_is_real.assert_zero(rounded * fadd.res_zero);
+
+    let ovf = eval_fp_round(
+        builder,
+        is_real.clone(),
+        &fadd.round,
+        &local.fprc_sel,
+        sig,
+        guard_limb,
+        lower_sum,
+        &fadd.rnd,
+        fadd.rnd[6].into(),
+        res_sign,
+    );
+
+    // the rounded exponent is 2047 or more if the normalized one is 2047 or it is 2046
+    // and the rounded significand carries out
+    let exp = fadd.exp;
+    let max_exp = fadd.exp_max;
+    let edge_exp = fadd.exp_edge;
+    let exp_ovf = fadd.exp_ovf;
+    let to_inf = local.fprc_sel[0] + fadd.round.away;
+    let mut when_is_real = builder.when(is_real.clone());
+    when_is_real.assert_eq(exp, x_exp_norm);
+    when_is_real.assert_bool(max_exp);
+    when_is_real.assert_bool(edge_exp);
+    when_is_real.assert_zero(max_exp * (exp - AB::F::from_canonical_u32(2047)));
+    when_is_real.assert_zero(edge_exp * (exp - AB::F::from_canonical_u32(2046)));
+    when_is_real.assert_eq(
+        (exp - AB::F::from_canonical_u32(2047)) * fadd.exp_max_inv,
+        one.clone() - max_exp,
+    );
+    when_is_real.assert_eq(
+        (exp - AB::F::from_canonical_u32(2046)) * fadd.exp_edge_inv,
+        one.clone() - edge_exp,
+    );
+    when_is_real.assert_eq(fadd.exp_above, max_exp + edge_exp * ovf.clone());
+    when_is_real.assert_eq(exp_ovf, rounded * fadd.exp_above);
+    when_is_real.assert_eq(fadd.to_max, exp_ovf * (one - to_inf.clone()));
+
+    let mut when_finite = builder.when(is_real.clone() * (rounded - exp_ovf));
+    for i in 0..6 {
+        when_finite.assert_eq(res[i], fadd.rnd[i]);
+    }
+    when_finite.assert_eq(res_mant_hi.clone(), fadd.rnd[6]);
+    when_finite.assert_eq(res_exp.clone(), exp + ovf);
+
+    // an overflow rounded away from zero is inf, otherwise it is the largest double
+    builder
+        .when(is_real.clone() * exp_ovf)
+        .assert_eq(fadd.res_inf, to_inf);
+    let mut when_max = builder.when(is_real * fadd.to_max);
+    for i in 0..6 {
+        when_max.assert_eq(res[i], AB::Expr::from_canonical_u32(255));
+    }
+    when_max.assert_eq(res_mant_hi, AB::Expr::from_canonical_u32(15));
+    when_max.assert_eq(res_exp, AB::Expr::from_canonical_u32(2046));
+}
+pub fn eval_fmul<AB: AirBuilder + AirBuilderWithPublicValues>(
+    builder: &mut AB,
+    local: &ProgExecCols<AB::Var>,
+    is_real: AB::Expr,
+) {
+    for lane in 0..LANES {
+        eval_fmul_lane(builder, local, lane, is_real.clone());
+    }
+}
+
+// The 106-bit product of the 53-bit significands has the leading bit at 104 or 105.
+// It is shifted by 4 or 3 bits to put the leading bit at 108, so the 53-bit significand
+// is in the limbs 7..14 and the guard bit is the top bit of the limb 6.
+pub fn eval_fmul_lane<AB: AirBuilder + AirBuilderWithPublicValues>(
+    builder: &mut AB,
+    local: &ProgExecCols<AB::Var>,
+    lane: usize,
+    is_real: AB::Expr,
+) {
+    let (left, right, res) = lane_operands(local, lane);
+    let cols = local.fp_lane(lane);
+    let fmul = cols.fmul();
+
+    let (left_sign, left_exp, left_mant_hi) = eval_fp_unpack(
+        builder,
+        is_real.clone(),
+        left,
+        &cols.left_bits,
+        &cols.left_inv,
+    );
+    let (right_sign, right_exp, right_mant_hi) = eval_fp_unpack(
+        builder,
+        is_real.clone(),
+        right,
+        &cols.right_bits,
+        &cols.right_inv,
+    );
+    let (res_sign, res_exp, res_mant_hi) =
+        eval_fp_unpack(builder, is_real.clone(), res, &cols.res_bits, &cols.res_inv);
+
+    let base = AB::F::from_canonical_u32(256);
+    let mut when_is_real = builder.when(is_real.clone());
+
+    let x_mant = fp_mant::<AB>(left, left_mant_hi);
+    let y_mant = fp_mant::<AB>(right, right_mant_hi);
+
+    // the carries are up to 2 bytes
+    let mut prev_carry = AB::Expr::zero();
+    for k in 0..14 {
+        let mut limb_sum = prev_carry;
+        for i in 0..7 {
+            if k >= i && k - i < 7 {
+                limb_sum = limb_sum + x_mant[i].clone() * y_mant[k - i].clone();
+            }
+        }
+        if k < 13 {
+            let carry = fmul.carry[2 * k] + fmul.carry[2 * k + 1] * base;
+            when_is_real.assert_eq(limb_sum, fmul.product[k] + carry.clone() * base);
+            prev_carry = carry;
+        } else {
+            when_is_real.assert_eq(limb_sum, fmul.product[k]);
+            prev_carry = AB::Expr::zero();
+        }
+    }
+
+    let top_hi = fmul.top[1];
+    when_is_real.assert_bool(fmul.top[0]);
+    when_is_real.assert_bool(top_hi);
+    when_is_real.assert_eq(fmul.product[13], fmul.top[0] + top_hi * AB::F::two());
+
+    // product * 2^(4 - top_hi), the top limb has no hi byte
+    let pow = AB::Expr::from_canonical_u32(16) - top_hi * AB::F::from_canonical_u32(8);
+    for k in 0..14 {
+        let shifted = fmul.product[k] * pow.clone();
+        if k < 13 {
+            when_is_real.assert_eq(shifted, fmul.lo[k] + fmul.hi[k] * base);
+        } else {
+            when_is_real.assert_eq(shifted, fmul.lo[k]);
+        }
+    }
+    let norm = |k: usize| -> AB::Expr {
+        if k == 0 {
+            fmul.lo[0].into()
+        } else {
+            fmul.lo[k] + fmul.hi[k - 1]
+        }
+    };
+    when_is_real.assert_eq(
+        res_sign.clone(),
+        left_sign.clone() + right_sign.clone() - left_sign * right_sign * AB::F::two(),
+    );
+
+    let mut lower_sum = AB::Expr::zero();
+    for k in 0..6 {
+        lower_sum = lower_sum + norm(k);
+    }
+    let sig = (7..14).map(norm).collect();
+    let ovf = eval_fp_round(
+        builder,
+        is_real.clone(),
+        &fmul.round,
+        &local.fprc_sel,
+        sig,
+        norm(6),
+        lower_sum,
+        res,
+        res_mant_hi,
+        res_sign,
+    );
+    builder.when(is_real).assert_eq(
+        res_exp,
+        left_exp + right_exp - AB::F::from_canonical_u32(1023) + top_hi + ovf,
+    );
+}
+
+pub fn eval_fdiv<AB: AirBuilder + AirBuilderWithPublicValues>(
+    builder: &mut AB,
+    local: &ProgExecCols<AB::Var>,
+    is_real: AB::Expr,
+) {
+    for lane in 0..LANES {
+        eval_fdiv_lane(builder, local, lane, is_real.clone());
+    }
+}
+
+// The left significand is shifted by 61 bits, or by 60 bits if it is not less than
+// the right one, so the quotient of the division by the right significand has
+// the leading bit at 60. The 53-bit significand is in the quotient limbs 1..8,
+// the guard bit is the top bit of the limb 0 and a nonzero remainder sets the sticky bit.
+pub fn eval_fdiv_lane<AB: AirBuilder + AirBuilderWithPublicValues>(
+    builder: &mut AB,
+    local: &ProgExecCols<AB::Var>,
+    lane: usize,
+    is_real: AB::Expr,
+) {
+    let (left, right, res) = lane_operands(local, lane);
+    let cols = local.fp_lane(lane);
+    let fdiv = cols.fdiv();
+
+    let (left_sign, left_exp, left_mant_hi) = eval_fp_unpack(
+        builder,
+        is_real.clone(),
+        left,
+        &cols.left_bits,
+        &cols.left_inv,
+    );
+    let (right_sign, right_exp, right_mant_hi) = eval_fp_unpack(
+        builder,
+        is_real.clone(),
+        right,
+        &cols.right_bits,
+        &cols.right_inv,
+    );
+    let (res_sign, res_exp, res_mant_hi) =
+        eval_fp_unpack(builder, is_real.clone(), res, &cols.res_bits, &cols.res_inv);
+
+    let base = AB::F::from_canonical_u32(256);
+    let mut when_is_real = builder.when(is_real.clone());
+
+    let x_mant = fp_mant::<AB>(left, left_mant_hi);
+    let y_mant = fp_mant::<AB>(right, right_mant_hi);
+
+    // rem < right significand
+    let mut prev_carry = AB::Expr::one();
+    for i in 0..7 {
+        let limb_sum = fdiv.rem[i] + fdiv.rem_diff[i] + prev_carry;
+        if i < 6 {
+            let carry = fdiv.rem_carry[i];
+            when_is_real.assert_bool(carry);
+            when_is_real.assert_eq(limb_sum, y_mant[i].clone() + carry * base);
+            prev_carry = carry.into();
+        } else {
+            when_is_real.assert_eq(limb_sum, y_mant[i].clone());
+            prev_carry = AB::Expr::zero();
+        }
+    }
+
+    // left significand * 2^(5 - shift) shifted by 7 limbs
+    let shift = fdiv.shift;
+    when_is_real.assert_bool(shift);
+    let pow = AB::Expr::from_canonical_u32(32) - shift * AB::F::from_canonical_u32(16);
+    let mut shifted = vec![AB::Expr::zero(); 15];
+    for i in 0..7 {
+        let lo = fdiv.lo[i];
+        let hi = fdiv.hi[i];
+        when_is_real.assert_eq(x_mant[i].clone() * pow.clone(), lo + hi * base);
+        shifted[i + 7] = shifted[i + 7].clone() + lo;
+        shifted[i + 8] = shifted[i + 8].clone() + hi;
+    }
+
+    // quotient * right significand + rem = shifted left significand, the carries are up to 2 bytes
+    for k in 0..15 {
+        let mut limb_sum = prev_carry;
+        for a in 0..8 {
+            if k >= a && k - a < 7 {
+                limb_sum = limb_sum + fdiv.quot[a] * y_mant[k - a].clone();
+            }
+        }
+        if k < 7 {
+            limb_sum = limb_sum + fdiv.rem[k];
+        }
+        if k < 14 {
+            let carry = fdiv.carry[2 * k] + fdiv.carry[2 * k + 1] * base;
+            when_is_real.assert_eq(limb_sum, shifted[k].clone() + carry.clone() * base);
+            prev_carry = carry;
+        } else {
+            when_is_real.assert_eq(limb_sum, shifted[k].clone());
+            prev_carry = AB::Expr::zero();
+        }
+    }
+    when_is_real.assert_eq(
+        res_sign.clone(),
+        left_sign.clone() + right_sign.clone() - left_sign * right_sign * AB::F::two(),
+    );
+
+    let mut lower_sum = AB::Expr::zero();
+    for i in 0..7 {
+        lower_sum = lower_sum + fdiv.rem[i];
+    }
+    let sig = (1..8).map(|i| fdiv.quot[i].into()).collect();
+    let guard_limb = fdiv.quot[0].into();
+    let ovf = eval_fp_round(
+        builder,
+        is_real.clone(),
+        &fdiv.round,
+        &local.fprc_sel,
+        sig,
+        guard_limb,
+        lower_sum,
+        res,
+        res_mant_hi,
+        res_sign,
+    );
+    builder.when(is_real).assert_eq(
+        res_exp,
+        left_exp - right_exp + AB::F::from_canonical_u32(1022) + shift + ovf,
+    );
+}
+
+// FDiv memory operand: every lane of the divisor is an int32 of the loaded value converted
+// to a double and masked by the E mask of the lane looked up with the instruction.
+// The mantissa of x is |x| * 2^(52 - p) - 2^52 where p is the leading bit of |x|,
+// the shift is split into a bit and a byte part like the FAdd alignment.
+pub fn eval_fdiv_mem<AB: SP1AirBuilder>(builder: &mut AB, local: &ProgExecCols<AB::Var>) {
+    let is_real = local.fp_mem_operand;
+    let base = AB::F::from_canonical_u32(256);
+
+    // the lane 0 int32 is the low half of the loaded value
+    let int_limbs: Vec<AB::Var> = (0..LANES)
+        .flat_map(|lane| local.fp_lane(lane).fdiv().mem.int)
+        .collect();
+    let value = pack_value::<AB>(&int_limbs);
+    for (k, packed) in value.into_iter().enumerate() {
+        builder.when(is_real).assert_eq(local.mem_val[k], packed);
+    }
+
+    for lane in 0..LANES {
+        let (_, right, _) = lane_operands(local, lane);
+        let mem = &local.fp_lane(lane).fdiv().mem;
+        let mut when_is_real = builder.when(is_real);
+
+        // |x| = x or 2^32 - x if the sign is set
+        let sign = mem.sign;
+        when_is_real.assert_bool(sign);
+        when_is_real.assert_eq(
+            mem.int[3],
+            mem.int_low7 + sign * AB::F::from_canonical_u32(128),
+        );
+        let mut prev_carry = AB::Expr::zero();
+        for i in 0..4 {
+            let carry: AB::Expr = if i < 3 {
+                when_is_real.assert_bool(mem.neg_carry[i]);
+                mem.neg_carry[i].into()
+            } else {
+                sign.into()
+            };
+            let int = mem.int[i];
+            let neg = carry.clone() * base - int * AB::F::two() - prev_carry;
+            when_is_real.assert_eq(mem.abs[i], int + sign * neg);
+            prev_carry = carry;
+        }
+
+        // no position is set if x is 0
+        let mut nonzero = AB::Expr::zero();
+        let mut pow = AB::Expr::zero();
+        let mut exp_low = AB::Expr::zero();
+        for p in 0..32 {
+            let sel = mem.pos[p];
+            when_is_real.assert_bool(sel);
+            nonzero = nonzero + sel;
+            pow = pow + sel * AB::F::from_canonical_u32(1 << ((52 - p) % 8));
+            // the low 4 bits of the biased exponent 1023 + p
+            exp_low = exp_low + sel * AB::F::from_canonical_usize((1023 + p) % 16);
+        }
+        when_is_real.assert_bool(nonzero.clone());
+        for i in 0..4 {
+            when_is_real.assert_zero(mem.abs[i] * (AB::Expr::one() - nonzero.clone()));
+            when_is_real.assert_eq(mem.abs[i] * pow.clone(), mem.lo[i] + mem.hi[i] * base);
+        }
+
+        // the limbs of |x| * 2^(52 - p) are the mantissa limbs and the leading bit at 52
+        for j in 0..11 {
+            let mut limb = AB::Expr::zero();
+            for p in 0..32 {
+                let q = (52 - p) / 8;
+                let mut part = AB::Expr::zero();
+                if j >= q && j - q < 4 {
+                    part = part + mem.lo[j - q];
+                }
+                if j > q && j - q - 1 < 4 {
+                    part = part + mem.hi[j - q - 1];
+                }
+                limb = limb + mem.pos[p] * part;
+            }
+            if j < 6 {
+                when_is_real.assert_eq(limb, mem.mant[j]);
+            } else if j == 6 {
+                let top = mem.mant[6] + nonzero.clone() * AB::F::from_canonical_u32(16);
+                when_is_real.assert_eq(limb, top);
+            } else {
+                when_is_real.assert_zero(limb);
+            }
+        }
+
+        // the masked divisor: the mask top limb, the exponent low bits and the mantissa,
+        // the low 22 bits ORed with the mask as a | b = (a + b + (a ^ b)) / 2
+        when_is_real.assert_eq(
+            right[7],
+            mem.mask_exp + AB::F::from_canonical_u64(E_MASK_EXP >> 56),
+        );
+        when_is_real.assert_eq(
+            right[6],
+            mem.mant[6] + exp_low * AB::F::from_canonical_u32(16),
+        );
+        for i in 3..6 {
+            when_is_real.assert_eq(right[i], mem.mant[i]);
+        }
+        for i in 0..3 {
+            when_is_real.assert_eq(
+                right[i] * AB::F::two(),
+                mem.mant[i] + mem.mask[i] + mem.xor[i],
+            );
+        }
+        when_is_real.assert_eq(
+            local.prog_e_mask[lane],
+            mem.mask[0]
+                + mem.mask[1] * base
+                + mem.mask[2] * AB::F::from_canonical_u32(1 << 16)
+                + mem.mask_exp * AB::F::from_canonical_u32(1 << 22),
+        );
+
+        for i in 0..3 {
+            let values = vec![
+                AB::Expr::from_canonical_u8(ByteOpcode::Xor as u8),
+                mem.mant[i].into(),
+                mem.mask[i].into(),
+                mem.xor[i].into(),
+            ];
+            builder.send(
+                AirInteraction::new(values, is_real.into(), InteractionKind::Byte),
+                InteractionScope::Local,
+            );
+        }
+    }
+}
+
+pub fn eval_fsqrt<AB: AirBuilder + AirBuilderWithPublicValues>(
+    builder: &mut AB,
+    local: &ProgExecCols<AB::Var>,
+    is_real: AB::Expr,
+) {
+    for lane in 0..LANES {
+        eval_fsqrt_lane(builder, local, lane, is_real.clone());
+    }
+}
+
+// The prover supplies the rounded root in the result. The left significand is shifted
+// by 68 bits, or by 69 bits if the unbiased exponent is odd, so the integer root has
+// the leading bit at 60 and root^2 <= shifted < (root + 1)^2. The 53-bit significand is
+// in the root limbs 1..8, the guard bit is the top bit of the limb 0 and a nonzero
+// remainder sets the sticky bit.
+pub fn eval_fsqrt_lane<AB: AirBuilder + AirBuilderWithPublicValues>(
+    builder: &mut AB,
+    local: &ProgExecCols<AB::Var>,
+    lane: usize,
+    is_real: AB::Expr,
+) {
+    let (left, _, res) = lane_operands(local, lane);
+    let cols = local.fp_lane(lane);
+    let fsqrt = cols.fsqrt();
+
+    let (left_sign, left_exp, left_mant_hi) = eval_fp_unpack(
+        builder,
+        is_real.clone(),
+        left,
+        &cols.left_bits,
+        &cols.left_inv,
+    );
+    let (res_sign, res_exp, res_mant_hi) =
+        eval_fp_unpack(builder, is_real.clone(), res, &cols.res_bits, &cols.res_inv);
+
+    let base = AB::F::from_canonical_u32(256);
+    let mut when_is_real = builder.when(is_real.clone());
+
+    // the input is positive and so is the root
+    when_is_real.assert_zero(left_sign);
+    when_is_real.assert_zero(res_sign.clone());
+
+    let x_mant = fp_mant::<AB>(left, left_mant_hi);
+
+    // rem <= 2 * root, the doubled root limbs make the carries -1, 0 or 1
+    let mut prev_carry = AB::Expr::zero();
+    for i in 0..8 {
+        let limb_sum = fsqrt.rem[i] + fsqrt.rem_diff[i] + prev_carry;
+        let root = fsqrt.root[i] * AB::F::two();
+        if i < 7 {
+            let carry_pos = fsqrt.rem_carry_pos[i];
+            let carry_neg = fsqrt.rem_carry_neg[i];
+            when_is_real.assert_bool(carry_pos);
+            when_is_real.assert_bool(carry_neg);
+            let carry = carry_pos - carry_neg;
+            when_is_real.assert_eq(limb_sum, root + carry.clone() * base);
+            prev_carry = carry;
+        } else {
+            when_is_real.assert_eq(limb_sum, root);
+            prev_carry = AB::Expr::zero();
+        }
+    }
+
+    // the lowest exponent bit is clear for odd unbiased exponents
+    let odd = AB::Expr::one() - cols.left_bits[4];
+    let pow = (odd.clone() + AB::F::one()) * AB::F::from_canonical_u32(16);
+    let mut shifted = vec![AB::Expr::zero(); 16];
+    for i in 0..7 {
+        let lo = fsqrt.lo[i];
+        let hi = fsqrt.hi[i];
+        when_is_real.assert_eq(x_mant[i].clone() * pow.clone(), lo + hi * base);
+        shifted[i + 8] = shifted[i + 8].clone() + lo;
+        shifted[i + 9] = shifted[i + 9].clone() + hi;
+    }
+
+    // root * root + rem = shifted left significand, the carries are up to 2 bytes
+    for k in 0..16 {
+        let mut limb_sum = prev_carry;
+        for a in 0..8 {
+            if k >= a && k - a < 8 {
+                limb_sum = limb_sum + fsqrt.root[a] * fsqrt.root[k - a];
+            }
+        }
+        if k < 8 {
+            limb_sum = limb_sum + fsqrt.rem[k];
+        }
+        if k < 15 {
+            let carry = fsqrt.carry[2 * k] + fsqrt.carry[2 * k + 1] * base;
+            when_is_real.assert_eq(limb_sum, shifted[k].clone() + carry.clone() * base);
+            prev_carry = carry;
+        } else {
+            when_is_real.assert_eq(limb_sum, shifted[k].clone());
+            prev_carry = AB::Expr::zero();
+        }
+    }
+
+    let mut lower_sum = AB::Expr::zero();
+    for i in 0..8 {
+        lower_sum = lower_sum + fsqrt.rem[i];
+    }
+    let sig = (1..8).map(|i| fsqrt.root[i].into()).collect();
+    let guard_limb = fsqrt.root[0].into();
+    let ovf = eval_fp_round(
+        builder,
+        is_real.clone(),
+        &fsqrt.round,
+        &local.fprc_sel,
+        sig,
+        guard_limb,
+        lower_sum,
+        res,
+        res_mant_hi,
+        res_sign,
+    );
+    builder.when(is_real).assert_eq(
+        (res_exp - ovf) * AB::F::two(),
+        left_exp + AB::F::from_canonical_u32(1023) - odd,
+    );
+}
+
+// The result is the left double XOR FSCAL_MASK. The masked bits are in the top 2 limbs,
+// the result is not required to be a normal double.
+pub fn eval_fscal<AB: AirBuilder + AirBuilderWithPublicValues>(
+    builder: &mut AB,
+    local: &ProgExecCols<AB::Var>,
+    is_real: AB::Expr,
+) {
+    for lane in 0..LANES {
+        let (left, _, res) = lane_operands(local, lane);
+        let bits = &local.fp_lane(lane).left_bits;
+        eval_fp_bits(builder, is_real.clone(), left, bits);
+
+        let mut when_is_real = builder.when(is_real.clone());
+
+        let mask_as_b = FSCAL_MASK.to_le_bytes();
+        for i in 0..8 {
+            if i < 6 {
+                when_is_real.assert_eq(res[i], left[i]);
+                continue;
+            }
+            let mut limb = AB::Expr::zero();
+            for j in 0..8 {
+                let bit = bits[8 * (i - 6) + j];
+                let pow = AB::F::from_canonical_u32(1 << j);
+                limb = if (mask_as_b[i] >> j) & 1 == 1 {
+                    limb + (AB::Expr::one() - bit) * pow
+                } else {
+                    limb + bit * pow
+                };
+            }
+            when_is_real.assert_eq(res[i], limb);
+        }
+    }
+}
+
+// the result lanes are the swapped left lanes
+pub fn eval_fswap<AB: AirBuilder + AirBuilderWithPublicValues>(
+    builder: &mut AB,
+    local: &ProgExecCols<AB::Var>,
+    is_real: AB::Expr,
+) {
+    let mut when_is_real = builder.when(is_real);
+
+    for i in 0..8 {
+        when_is_real.assert_eq(local.result[i], local.left_arg_hi[i]);
+        when_is_real.assert_eq(local.result_hi[i], local.left_arg[i]);
+    }
+}
+
+// Rounds the exact result of an op in the RandomX mode fprc. r is the result rounded
+// to nearest, ties to even and err has the sign of the exact result - r.
+pub fn fp_round_mode(r: f64, err: f64, fprc: u32) -> f64 {
+    let toward_zero = (err > 0.0) != (r > 0.0);
+    let step = match fprc {
+        1 => err < 0.0,
+        2 => err > 0.0,
+        3 => err != 0.0 && toward_zero,
+        _ => false,
+    };
+    if !step {
+        return r;
+    }
+    // the next double from r in the err direction, an underflow steps to a denormal
+    if r == 0.0 {
+        f64::from_bits(1).copysign(err)
+    } else if toward_zero {
+        f64::from_bits(r.to_bits() - 1)
+    } else {
+        f64::from_bits(r.to_bits() + 1)
+    }
+}
+
+// Overflow of a sum rounded to nearest to inf r. The modes rounding toward zero give
+// the largest double of the sign instead.
+fn fp_overflow(r: f64, fprc: u32) -> f64 {
+    let to_inf = match fprc {
+        1 => r < 0.0,
+        2 => r > 0.0,
+        3 => false,
+        _ => true,
+    };
+    if to_inf {
+        r
+    } else {
+        f64::MAX.copysign(r)
+    }
+}
+
+// FAdd, FSub, FMul, FDiv and FSqrt of a lane in the rounding mode fprc. The rounding
+// error of the nearest result is exact so its sign selects the rounding direction,
+// tiny operands are scaled by 2^600 so the error does not underflow.
+pub fn fp_compute(op: I64MathOps, left: f64, right: f64, fprc: u32) -> f64 {
+    let scale = f64::from_bits((1023 + 600) << 52);
+    let tiny = f64::from_bits((1023 - 900) << 52);
+    let right = match op {
+        I64MathOps::FSub => -right,
+        _ => right,
+    };
+    let (r, err) = match op {
+        I64MathOps::FAdd | I64MathOps::FSub => {
+            let r = left + right;
+            if left.is_infinite() {
+                return left;
+            }
+            if r.is_infinite() {
+                return fp_overflow(r, fprc);
+            }
+            // exact cancellation is -0 when rounding down
+            if r == 0.0 {
+                return if fprc == 1 { -0.0 } else { 0.0 };
+            }
+            let right_part = r - left;
+            (r, (left - (r - right_part)) + (right - right_part))
+        }
+        I64MathOps::FMul => {
+            let r = left * right;
+            let (x, y) = if left.abs() < right.abs() {
+                (left, right)
+            } else {
+                (right, left)
+            };
+            if r.abs() < tiny {
+                (r, (x * scale).mul_add(y, -(r * scale)))
+            } else {
+                (r, x.mul_add(y, -r))
+            }
+        }
+        I64MathOps::FDiv => {
+            let r = left / right;
+            let resid = if left.abs() < tiny {
+                (-r * scale).mul_add(right, left * scale)
+            } else {
+                (-r).mul_add(right, left)
+            };
+            (r, resid * right.signum())
+        }
+        I64MathOps::FSqrt => {
+            let r = left.sqrt();
+            if left < tiny {
+                let root_scale = f64::from_bits((1023 + 300) << 52);
+                let scaled = r * root_scale;
+                (r, (-scaled).mul_add(scaled, left * scale))
+            } else {
+                (r, (-r).mul_add(r, left))
+            }
+        }
+        _ => unreachable!("not a rounded FP op"),
+    };
+    fp_round_mode(r, err, fprc)
+}
+
+// 7 limbs of the 53-bit significand with the implicit leading bit
+fn fp_mant<AB: AirBuilder>(z: &[AB::Var; 8], mant_hi: AB::Expr) -> Vec<AB::Expr> {
+    let mut mant: Vec<AB::Expr> = (0..6).map(|i| z[i].into()).collect();
+    mant.push(mant_hi + AB::F::from_canonical_u32(16));
+    mant
+}
+
+// Rounds the 53-bit significand in the 7 sig limbs, the top one in [16, 32), in the rounding
+// mode of the row. guard_limb is the limb below the significand and lower_sum is the sum
+// of the limbs below it. The rounded mantissa is asserted to be in the res limbs,
+// returns the exponent increment of a carry out of the significand.
+#[allow(clippy::too_many_arguments)]
+pub fn eval_fp_round<AB: AirBuilder + AirBuilderWithPublicValues>(
+    builder: &mut AB,
+    is_real: AB::Expr,
+    round: &FpRoundCols<AB::Var>,
+    fprc_sel: &[AB::Var; 4],
+    sig: Vec<AB::Expr>,
+    guard_limb: AB::Expr,
+    lower_sum: AB::Expr,
+    res: &[AB::Var],
+    res_mant_hi: AB::Expr,
+    res_sign: AB::Expr,
+) -> AB::Expr {
+    let base = AB::F::from_canonical_u32(256);
+    let one = AB::Expr::one();
+    let mut when_is_real = builder.when(is_real);
+
+    let mut top = AB::Expr::zero();
+    for j in 0..4 {
+        when_is_real.assert_bool(round.top_bits[j]);
+        top = top + round.top_bits[j] * AB::F::from_canonical_u32(1 << j);
+    }
+    when_is_real.assert_eq(sig[6].clone(), top.clone() + AB::F::from_canonical_u32(16));
+
+    let mut guard_bits = AB::Expr::zero();
+    let mut sticky_sum = lower_sum;
+    for j in 0..8 {
+        let bit = round.guard_bits[j];
+        when_is_real.assert_bool(bit);
+        guard_bits = guard_bits + bit * AB::F::from_canonical_u32(1 << j);
+        if j < 7 {
+            sticky_sum = sticky_sum + bit * AB::F::from_canonical_u32(1 << j);
+        }
+    }
+    when_is_real.assert_eq(guard_limb, guard_bits);
+    let guard = round.guard_bits[7];
+
+    // is-nonzero gadget
+    let sticky = round.sticky;
+    when_is_real.assert_bool(sticky);
+    when_is_real.assert_zero(sticky_sum.clone() * (one.clone() - sticky));
+    when_is_real.assert_eq(sticky, sticky_sum * round.sticky_inv);
+
+    let lsb = round.lsb;
+    when_is_real.assert_bool(lsb);
+    when_is_real.assert_eq(sig[0].clone(), lsb + round.lsb_half * AB::F::two());
+    when_is_real.assert_eq(round.sticky_or_lsb, sticky + lsb - sticky * lsb);
+
+    // RandomX modes are to nearest with ties to even, down, up and toward zero
+    when_is_real.assert_eq(round.inc_nearest, guard * round.sticky_or_lsb);
+    when_is_real.assert_eq(round.inexact, guard + sticky - guard * sticky);
+    when_is_real.assert_eq(
+        round.away,
+        fprc_sel[1] * res_sign.clone() + fprc_sel[2] * (one - res_sign),
+    );
+    when_is_real.assert_eq(
+        round.inc,
+        fprc_sel[0] * round.inc_nearest + round.away * round.inexact,
+    );
+
+    // a carry out of the rounded significand bumps the exponent
+    let mut prev_carry: AB::Expr = round.inc.into();
+    for i in 0..6 {
+        let carry = round.res_carry[i];
+        when_is_real.assert_bool(carry);
+        when_is_real.assert_eq(sig[i].clone() + prev_carry, res[i] + carry * base);
+        prev_carry = carry.into();
+    }
+    let ovf = round.ovf;
+    when_is_real.assert_bool(ovf);
+    when_is_real.assert_eq(
+        top + prev_carry,
+        res_mant_hi + ovf * AB::F::from_canonical_u32(16),
+    );
+
+    ovf.into()
+}
+
+pub fn populate_fp_operands_trace_record<F: Field>(
+    op: I64MathOps,
+    cnt: u32,
+    left: [f64; 2],
+    right: [f64; 2],
+    res: [f64; 2],
+) -> Vec<F> {
+    let mut trace_record = populate_operands_trace_record(
+        op,
+        cnt,
+        left[0].to_bits() as i64,
+        right[0].to_bits() as i64,
+        res[0].to_bits() as i64,
+    );
+    let cols: &mut ProgExecCols<F> = trace_record.as_mut_slice().borrow_mut();
+
+    cols.left_arg_hi = left[1].to_bits().to_le_bytes().map(F::from_canonical_u8);
+    cols.right_arg_hi = right[1].to_bits().to_le_bytes().map(F::from_canonical_u8);
+    cols.result_hi = res[1].to_bits().to_le_bytes().map(F::from_canonical_u8);
+
+    trace_record
+}
+
+pub fn populate_fp_bits<F: Field>(bits: &mut [F; 16], z: u64) {
+    for j in 0..16 {
+        bits[j] = F::from_canonical_u64((z >> (48 + j)) & 1);
+    }
+}
+
+pub fn populate_fp_unpack<F: Field>(bits: &mut [F; 16], inv: &mut [F; 2], z: u64) {
+    populate_fp_bits(bits, z);
+    let exp = (z >> 52) & 0x7ff;
+    inv[0] = F::from_canonical_u64(exp)
+        .try_inverse()
+        .unwrap_or(F::zero());
+    inv[1] = F::from_canonical_u64(2047 - exp)
+        .try_inverse()
+        .unwrap_or(F::zero());
+}
+
+// the left, right and result doubles of the lane
+fn populate_lane_unpack<F: Field>(cols: &mut FpLaneCols<F>, left: u64, right: u64, res: u64) {
+    populate_fp_unpack(&mut cols.left_bits, &mut cols.left_inv, left);
+    populate_fp_unpack(&mut cols.right_bits, &mut cols.right_inv, right);
+    populate_fp_unpack(&mut cols.res_bits, &mut cols.res_inv, res);
+}
+
+fn populate_one_hot<F: Field>(sel: &mut [F], value: usize) {
+    sel[value] = F::one();
+}
+
+// carries are -1, 0 or 1
+fn carry_to_field<F: Field>(carry: i32) -> F {
+    if carry < 0 {
+        F::neg_one()
+    } else {
+        F::from_canonical_u32(carry as u32)
+    }
+}
+
+pub fn populate_fadd_trace_record<F: Field>(
+    op: I64MathOps,
+    cnt: u32,
+    fprc: u32,
+    left: [f64; 2],
+    right: [f64; 2],
+    res: [f64; 2],
+) -> Vec<F> {
+    let mut trace_record = populate_fp_operands_trace_record(op, cnt, left, right, res);
+    let cols: &mut ProgExecCols<F> = trace_record.as_mut_slice().borrow_mut();
+    let is_sub = matches!(op, I64MathOps::FSub);
+
+    for lane in 0..LANES {
+        populate_fadd_lane(
+            cols.fp_lane_mut(lane),
+            left[lane].to_bits(),
+            right[lane].to_bits(),
+            is_sub,
+            res[lane].to_bits(),
+            fprc,
+        );
+    }
+
+    trace_record
+}
+
+pub fn populate_fadd_lane<F: Field>(
+    cols: &mut FpLaneCols<F>,
+    left: u64,
+    right: u64,
+    is_sub: bool,
+    res: u64,
+    fprc: u32,
+) {
+    populate_lane_unpack(cols, left, right, res);
+    let fadd = cols.fadd_mut();
+    populate_fp_special(&mut fadd.left_zero, &mut fadd.left_inf, left);
+    populate_fp_special(&mut fadd.res_zero, &mut fadd.res_inf, res);
+
+    let sign = if is_sub { right ^ SIGN_BIT } else { right };
+    let swap = (left & !SIGN_BIT) < (right & !SIGN_BIT);
+    let (x, y) = if swap { (sign, left) } else { (left, sign) };
+    let op_sub = (x ^ y) & SIGN_BIT != 0;
+    fadd.sign = F::from_bool(sign & SIGN_BIT != 0);
+    fadd.op_sub = F::from_bool(op_sub);
+
+    let left_mag = (left & !SIGN_BIT).to_le_bytes();
+    let right_mag = (right & !SIGN_BIT).to_le_bytes();
+    let mut borrow = 0i32;
+    for i in 0..8 {
+        let diff = left_mag[i] as i32 - right_mag[i] as i32 - borrow;
+        borrow = (diff < 0) as i32;
+        fadd.cmp[i] = F::from_canonical_u32((diff + 256 * borrow) as u32);
+        fadd.borrow[i] = F::from_canonical_u32(borrow as u32);
+    }
+
+    let exp_diff = ((x >> 52) & 0x7ff) - ((y >> 52) & 0x7ff);
+    let align = exp_diff.min(63);
+    fadd.exp_diff = F::from_canonical_u64(exp_diff);
+    populate_one_hot(&mut fadd.align_q, (align / 8) as usize);
+    populate_one_hot(&mut fadd.align_r, (align % 8) as usize);
+    if exp_diff > 63 {
+        fadd.cap = F::one();
+        fadd.cap_diff[0] = F::from_canonical_u64((exp_diff - 63) & 0xff);
+        fadd.cap_diff[1] = F::from_canonical_u64((exp_diff - 63) >> 8);
+    }
+
+    let x_mant = (x & MANT_BITS) | (1 << 52);
+    let y_mant = (y & MANT_BITS) | (1 << 52);
+    let y_mant_as_b = y_mant.to_le_bytes();
+    for i in 0..7 {
+        let shifted = (y_mant_as_b[i] as u32) << (8 - align % 8);
+        fadd.mant_y[i] = F::from_canonical_u8(y_mant_as_b[i]);
+        fadd.align_lo[i] = F::from_canonical_u32(shifted & 0xff);
+        fadd.align_hi[i] = F::from_canonical_u32(shifted >> 8);
+    }
+
+    let x_shifted = (x_mant as u128) << 64;
+    let aligned = ((y_mant as u128) << 64) >> align;
+    let sum = if op_sub {
+        x_shifted - aligned
+    } else {
+        x_shifted + aligned
+    };
+
+    let x_as_b = x_shifted.to_le_bytes();
+    let aligned_as_b = aligned.to_le_bytes();
+    let sum_as_b = sum.to_le_bytes();
+    let mut carry = 0i32;
+    for i in 0..16 {
+        fadd.aligned[i] = F::from_canonical_u8(aligned_as_b[i]);
+        fadd.sum[i] = F::from_canonical_u8(sum_as_b[i]);
+        if i < 15 {
+            let addend = if op_sub {
+                -(aligned_as_b[i] as i32)
+            } else {
+                aligned_as_b[i] as i32
+            };
+            carry = (x_as_b[i] as i32 + addend + carry).div_euclid(256);
+            fadd.sum_carry[i] = carry_to_field(carry);
+        }
+    }
+
+    let norm_exp = sum.leading_zeros() - 3;
+    let norm = sum << norm_exp;
+    let norm_as_b = norm.to_le_bytes();
+    populate_one_hot(&mut fadd.norm_q, (norm_exp / 8) as usize);
+    populate_one_hot(&mut fadd.norm_r, (norm_exp % 8) as usize);
+    for i in 0..16 {
+        let shifted = (sum_as_b[i] as u32) << (norm_exp % 8);
+        fadd.norm_lo[i] = F::from_canonical_u32(shifted & 0xff);
+        fadd.norm_hi[i] = F::from_canonical_u32(shifted >> 8);
+        fadd.norm[i] = F::from_canonical_u8(norm_as_b[i]);
+    }
+
+    let lower_sum = norm_as_b[..8].iter().map(|&b| b as u32).sum();
+    let inc = populate_fp_round(
+        &mut fadd.round,
+        &norm_as_b[9..16],
+        norm_as_b[8],
+        lower_sum,
+        res >> 63 == 1,
+        fprc,
+    );
+    let rnd = ((norm >> 72) as u64 & ((1 << 53) - 1)) + inc as u64;
+    for i in 0..6 {
+        fadd.rnd[i] = F::from_canonical_u64((rnd >> (8 * i)) & 0xff);
+    }
+    fadd.rnd[6] = F::from_canonical_u64((rnd >> 48) & 0xf);
+
+    // zero and infinite left operands and exact cancellations do not take the rounded sum
+    let left_exp = (left >> 52) & 0x7ff;
+    let cancel = sum == 0;
+    let rounded = !cancel && left_exp != 0 && left_exp != 0x7ff;
+    fadd.cancel = F::from_bool(cancel);
+    fadd.rounded = F::from_bool(rounded);
+
+    // the exponent is negative for a cancelled sum
+    let exp = ((x >> 52) & 0x7ff) as i64 + 8 - norm_exp as i64;
+    let ovf = (rnd >> 53) as i64;
+    assert!(
+        !rounded || exp + ovf > 0,
+        "subnormal FP results are not supported"
+    );
+    let exp_as_f = F::from_canonical_u64((x >> 52) & 0x7ff) + F::from_canonical_u32(8)
+        - F::from_canonical_u32(norm_exp);
+    let above = exp == 2047 || (exp == 2046 && ovf == 1);
+    let exp_ovf = rounded && above;
+    let to_inf = match fprc {
+        0 => true,
+        1 => res >> 63 == 1,
+        2 => res >> 63 == 0,
+        _ => false,
+    };
+    fadd.exp = exp_as_f;
+    fadd.exp_max = F::from_bool(exp == 2047);
+    fadd.exp_max_inv = (exp_as_f - F::from_canonical_u32(2047))
+        .try_inverse()
+        .unwrap_or(F::zero());
+    fadd.exp_edge = F::from_bool(exp == 2046);
+    fadd.exp_edge_inv = (exp_as_f - F::from_canonical_u32(2046))
+        .try_inverse()
+        .unwrap_or(F::zero());
+    fadd.exp_above = F::from_bool(above);
+    fadd.exp_ovf = F::from_bool(exp_ovf);
+    fadd.to_max = F::from_bool(exp_ovf && !to_inf);
+}
+
+// flags of a zero or infinite double
+pub fn populate_fp_special<F: Field>(zero: &mut F, inf: &mut F, z: u64) {
+    *zero = F::from_bool(z & !SIGN_BIT == 0);
+    *inf = F::from_bool(z & !SIGN_BIT == 0x7ff << 52);
+}
+
+// returns the rounding increment
+pub fn populate_fp_round<F: Field>(
+    round: &mut FpRoundCols<F>,
+    sig: &[u8],
+    guard_limb: u8,
+    lower_sum: u32,
+    res_sign: bool,
+    fprc: u32,
+) -> u32 {
+    for j in 0..8 {
+        round.guard_bits[j] = F::from_canonical_u8((guard_limb >> j) & 1);
+    }
+    for j in 0..4 {
+        round.top_bits[j] = F::from_canonical_u8((sig[6] >> j) & 1);
+    }
+
+    let guard = (guard_limb >> 7) as u32;
+    let lsb = (sig[0] & 1) as u32;
+    let sticky_sum = lower_sum + (guard_limb & 0x7f) as u32;
+    let sticky = (sticky_sum != 0) as u32;
+    let inc_nearest = guard & (sticky | lsb);
+    let inexact = guard | sticky;
+    let away = (fprc == 1 && res_sign) || (fprc == 2 && !res_sign);
+    let inc = if fprc == 0 {
+        inc_nearest
+    } else {
+        inexact & away as u32
+    };
+    round.inc_nearest = F::from_canonical_u32(inc_nearest);
+    round.inexact = F::from_canonical_u32(inexact);
+    round.away = F::from_bool(away);
+    round.lsb = F::from_canonical_u32(lsb);
+    round.lsb_half = F::from_canonical_u8(sig[0] >> 1);
+    round.sticky = F::from_canonical_u32(sticky);
+    round.sticky_inv = F::from_canonical_u32(sticky_sum)
+        .try_inverse()
+        .unwrap_or(F::zero());
+    round.sticky_or_lsb = F::from_canonical_u32(sticky | lsb);
+    round.inc = F::from_canonical_u32(inc);
+
+    let mut carry = inc;
+    for i in 0..6 {
+        carry = (sig[i] as u32 + carry) >> 8;
+        round.res_carry[i] = F::from_canonical_u32(carry);
+    }
+    round.ovf = F::from_canonical_u32(((sig[6] & 0xf) as u32 + carry) >> 4);
+
+    inc
+}
+
+pub fn populate_fmul_trace_record<F: Field>(
+    op: I64MathOps,
+    cnt: u32,
+    fprc: u32,
+    left: [f64; 2],
+    right: [f64; 2],
+    res: [f64; 2],
+) -> Vec<F> {
+    let mut trace_record = populate_fp_operands_trace_record(op, cnt, left, right, res);
+    let cols: &mut ProgExecCols<F> = trace_record.as_mut_slice().borrow_mut();
+
+    for lane in 0..LANES {
+        populate_fmul_lane(
+            cols.fp_lane_mut(lane),
+            left[lane].to_bits(),
+            right[lane].to_bits(),
+            res[lane].to_bits(),
+            fprc,
+        );
+    }
+
+    trace_record
+}
+
+pub fn populate_fmul_lane<F: Field>(
+    cols: &mut FpLaneCols<F>,
+    left: u64,
+    right: u64,
+    res: u64,
+    fprc: u32,
+) {
+    populate_lane_unpack(cols, left, right, res);
+    let fmul = cols.fmul_mut();
+
+    let x_mant = (left & MANT_BITS) | (1 << 52);
+    let y_mant = (right & MANT_BITS) | (1 << 52);
+    let x_as_b = x_mant.to_le_bytes();
+    let y_as_b = y_mant.to_le_bytes();
+    let product = x_mant as u128 * y_mant as u128;
+    let product_as_b = product.to_le_bytes();
+
+    let mut carry = 0u64;
+    for k in 0..14 {
+        let mut limb_sum = carry;
+        for i in 0..7 {
+            if k >= i && k - i < 7 {
+                limb_sum += x_as_b[i] as u64 * y_as_b[k - i] as u64;
+            }
+        }
+        carry = limb_sum >> 8;
+        fmul.product[k] = F::from_canonical_u8(product_as_b[k]);
+        if k < 13 {
+            fmul.carry[2 * k] = F::from_canonical_u64(carry & 0xff);
+            fmul.carry[2 * k + 1] = F::from_canonical_u64(carry >> 8);
+        }
+    }
+
+    let top_hi = (product >> 105) as u32;
+    fmul.top[0] = F::from_canonical_u32(((product >> 104) & 1) as u32);
+    fmul.top[1] = F::from_canonical_u32(top_hi);
+    for k in 0..14 {
+        let shifted = (product_as_b[k] as u32) << (4 - top_hi);
+        fmul.lo[k] = F::from_canonical_u32(shifted & 0xff);
+        if k < 13 {
+            fmul.hi[k] = F::from_canonical_u32(shifted >> 8);
+        }
+    }
+
+    let norm_as_b = (product << (4 - top_hi)).to_le_bytes();
+    let lower_sum = norm_as_b[..6].iter().map(|&b| b as u32).sum();
+    populate_fp_round(
+        &mut fmul.round,
+        &norm_as_b[7..14],
+        norm_as_b[6],
+        lower_sum,
+        res >> 63 == 1,
+        fprc,
+    );
+}
+
+// the divisor is either an A group register or a loaded memory operand
+pub fn populate_fdiv_trace_record<F: Field>(
+    op: I64MathOps,
+    cnt: u32,
+    fprc: u32,
+    left: [f64; 2],
+    right: [f64; 2],
+    res: [f64; 2],
+) -> Vec<F> {
+    let mut trace_record = populate_fp_operands_trace_record(op, cnt, left, right, res);
+    let cols: &mut ProgExecCols<F> = trace_record.as_mut_slice().borrow_mut();
+
+    for lane in 0..LANES {
+        populate_fdiv_lane(
+            cols.fp_lane_mut(lane),
+            left[lane].to_bits(),
+            right[lane].to_bits(),
+            res[lane].to_bits(),
+            fprc,
+        );
+    }
+
+    trace_record
+}
+
+pub fn populate_fdiv_lane<F: Field>(
+    cols: &mut FpLaneCols<F>,
+    left: u64,
+    right: u64,
+    res: u64,
+    fprc: u32,
+) {
+    populate_lane_unpack(cols, left, right, res);
+    let fdiv = cols.fdiv_mut();
+
+    let x_mant = (left & MANT_BITS) | (1 << 52);
+    let y_mant = (right & MANT_BITS) | (1 << 52);
+    let shift = (x_mant >= y_mant) as u32;
+    let shifted = (x_mant as u128) << (61 - shift);
+    let quot = shifted / y_mant as u128;
+    let rem = (shifted % y_mant as u128) as u64;
+    let quot_as_b = quot.to_le_bytes();
+    let rem_as_b = rem.to_le_bytes();
+    let rem_diff_as_b = (y_mant - rem - 1).to_le_bytes();
+    let x_as_b = x_mant.to_le_bytes();
+    let y_as_b = y_mant.to_le_bytes();
+
+    fdiv.shift = F::from_canonical_u32(shift);
+    let mut carry = 1u32;
+    for i in 0..7 {
+        fdiv.rem[i] = F::from_canonical_u8(rem_as_b[i]);
+        fdiv.rem_diff[i] = F::from_canonical_u8(rem_diff_as_b[i]);
+        carry = (rem_as_b[i] as u32 + rem_diff_as_b[i] as u32 + carry) >> 8;
+        if i < 6 {
+            fdiv.rem_carry[i] = F::from_canonical_u32(carry);
+        }
+
+        let shifted = (x_as_b[i] as u32) << (5 - shift);
+        fdiv.lo[i] = F::from_canonical_u32(shifted & 0xff);
+        fdiv.hi[i] = F::from_canonical_u32(shifted >> 8);
+    }
+
+    let mut carry = 0u64;
+    for k in 0..14 {
+        let mut limb_sum = carry;
+        for a in 0..8 {
+            if k >= a && k - a < 7 {
+                limb_sum += quot_as_b[a] as u64 * y_as_b[k - a] as u64;
+            }
+        }
+        if k < 7 {
+            limb_sum += rem_as_b[k] as u64;
+        }
+        carry = limb_sum >> 8;
+        fdiv.carry[2 * k] = F::from_canonical_u64(carry & 0xff);
+        fdiv.carry[2 * k + 1] = F::from_canonical_u64(carry >> 8);
+    }
+    for a in 0..8 {
+        fdiv.quot[a] = F::from_canonical_u8(quot_as_b[a]);
+    }
+
+    let lower_sum = rem_as_b[..7].iter().map(|&b| b as u32).sum();
+    populate_fp_round(
+        &mut fdiv.round,
+        &quot_as_b[1..8],
+        quot_as_b[0],
+        lower_sum,
+        res >> 63 == 1,
+        fprc,
+    );
+}
+
+// the divisor lanes are the int32 halves of the loaded value converted and masked
+pub fn fdiv_mem_operand(value: u64, e_mask: [u64; 2]) -> [f64; 2] {
+    [0, 1].map(|i| {
+        let x = (value >> (32 * i)) as u32 as i32 as f64;
+        f64::from_bits(x.to_bits() & E_MASK_KEPT_BITS | e_mask[i])
+    })
+}
+
+pub fn is_e_mask(e_mask: u64) -> bool {
+    e_mask & !E_MASK_VAR_BITS == E_MASK_EXP
+}
+
+// the program table word of an E mask: the 4 variable exponent bits above the low 22 bits
+pub fn e_mask_word(e_mask: u64) -> u32 {
+    ((e_mask >> 56) as u32 & 0xf) << 22 | (e_mask & 0x3f_ffff) as u32
+}
+
+pub fn populate_fdiv_mem<F: Field>(cols: &mut ProgExecCols<F>, value: u64, e_mask: [u64; 2]) {
+    cols.fp_mem_operand = F::one();
+    for lane in 0..LANES {
+        let x = (value >> (32 * lane)) as u32;
+        let int_as_b = x.to_le_bytes();
+        let abs = (x as i32).unsigned_abs();
+        let abs_as_b = abs.to_le_bytes();
+        let sign = x >> 31;
+        let mant = (x as i32 as f64).to_bits();
+        let mant_as_b = mant.to_le_bytes();
+        let mask_as_b = e_mask[lane].to_le_bytes();
+
+        cols.prog_e_mask[lane] = F::from_canonical_u32(e_mask_word(e_mask[lane]));
+        let mem = &mut cols.fp_lane_mut(lane).fdiv_mut().mem;
+
+        mem.sign = F::from_canonical_u32(sign);
+        mem.int_low7 = F::from_canonical_u8(int_as_b[3] & 0x7f);
+        let mut carry = 0u32;
+        for i in 0..4 {
+            mem.int[i] = F::from_canonical_u8(int_as_b[i]);
+            mem.abs[i] = F::from_canonical_u8(abs_as_b[i]);
+            if sign == 1 && i < 3 {
+                carry = (abs_as_b[i] as u32 + int_as_b[i] as u32 + carry) >> 8;
+                mem.neg_carry[i] = F::from_canonical_u32(carry);
+            }
+        }
+
+        if abs != 0 {
+            let p = 31 - abs.leading_zeros() as usize;
+            mem.pos[p] = F::one();
+            for i in 0..4 {
+                let shifted = (abs_as_b[i] as u32) << ((52 - p) % 8);
+                mem.lo[i] = F::from_canonical_u32(shifted & 0xff);
+                mem.hi[i] = F::from_canonical_u32(shifted >> 8);
+            }
+        }
+        for i in 0..6 {
+            mem.mant[i] = F::from_canonical_u8(mant_as_b[i]);
+        }
+        mem.mant[6] = F::from_canonical_u8(mant_as_b[6] & 0xf);
+
+        for i in 0..3 {
+            let mask = if i < 2 {
+                mask_as_b[i]
+            } else {
+                mask_as_b[i] & 0x3f
+            };
+            mem.mask[i] = F::from_canonical_u8(mask);
+            mem.xor[i] = F::from_canonical_u8(mant_as_b[i] ^ mask);
+        }
+        mem.mask_exp = F::from_canonical_u8(mask_as_b[7] & 0xf);
+    }
+}
+
+pub fn populate_fsqrt_trace_record<F: Field>(
+    op: I64MathOps,
+    cnt: u32,
+    fprc: u32,
+    left: [f64; 2],
+    res: [f64; 2],
+) -> Vec<F> {
+    let mut trace_record = populate_fp_operands_trace_record(op, cnt, left, [0.0; 2], res);
+    let cols: &mut ProgExecCols<F> = trace_record.as_mut_slice().borrow_mut();
+
+    for lane in 0..LANES {
+        populate_fsqrt_lane(
+            cols.fp_lane_mut(lane),
+            left[lane].to_bits(),
+            res[lane].to_bits(),
+            fprc,
+        );
+    }
+
+    trace_record
+}
+
+pub fn populate_fsqrt_lane<F: Field>(cols: &mut FpLaneCols<F>, left: u64, res: u64, fprc: u32) {
+    populate_fp_unpack(&mut cols.left_bits, &mut cols.left_inv, left);
+    populate_fp_unpack(&mut cols.res_bits, &mut cols.res_inv, res);
+    let fsqrt = cols.fsqrt_mut();
+
+    let x_mant = (left & MANT_BITS) | (1 << 52);
+    let odd = 1 - ((left >> 52) & 1) as u32;
+    let shifted = (x_mant as u128) << (68 + odd);
+    // the f64 estimate is off by a few hundreds at most
+    let mut root = (shifted as f64).sqrt() as u128;
+    while root * root > shifted {
+        root -= 1;
+    }
+    while (root + 1) * (root + 1) <= shifted {
+        root += 1;
+    }
+    let rem = shifted - root * root;
+    let root_as_b = root.to_le_bytes();
+    let rem_as_b = rem.to_le_bytes();
+    let rem_diff_as_b = (2 * root - rem).to_le_bytes();
+    let x_as_b = x_mant.to_le_bytes();
+
+    let mut carry = 0i32;
+    for i in 0..8 {
+        fsqrt.root[i] = F::from_canonical_u8(root_as_b[i]);
+        fsqrt.rem[i] = F::from_canonical_u8(rem_as_b[i]);
+        fsqrt.rem_diff[i] = F::from_canonical_u8(rem_diff_as_b[i]);
+        carry =
+            (rem_as_b[i] as i32 + rem_diff_as_b[i] as i32 + carry - 2 * root_as_b[i] as i32) / 256;
+        if i < 7 {
+            fsqrt.rem_carry_pos[i] = F::from_bool(carry > 0);
+            fsqrt.rem_carry_neg[i] = F::from_bool(carry < 0);
+        }
+    }
+    for i in 0..7 {
+        let shifted = (x_as_b[i] as u32) << (4 + odd);
+        fsqrt.lo[i] = F::from_canonical_u32(shifted & 0xff);
+        fsqrt.hi[i] = F::from_canonical_u32(shifted >> 8);
+    }
+
+    let mut carry = 0u64;
+    for k in 0..15 {
+        let mut limb_sum = carry;
+        for a in 0..8 {
+            if k >= a && k - a < 8 {
+                limb_sum += root_as_b[a] as u64 * root_as_b[k - a] as u64;
+            }
+        }
+        if k < 8 {
+            limb_sum += rem_as_b[k] as u64;
+        }
+        carry = limb_sum >> 8;
+        fsqrt.carry[2 * k] = F::from_canonical_u64(carry & 0xff);
+        fsqrt.carry[2 * k + 1] = F::from_canonical_u64(carry >> 8);
+    }
+
+    let lower_sum = rem_as_b[..8].iter().map(|&b| b as u32).sum();
+    populate_fp_round(
+        &mut fsqrt.round,
+        &root_as_b[1..8],
+        root_as_b[0],
+        lower_sum,
+        res >> 63 == 1,
+        fprc,
+    );
+}
+
+pub fn populate_fscal_trace_record<F: Field>(
+    op: I64MathOps,
+    cnt: u32,
+    left: [f64; 2],
+    res: [f64; 2],
+) -> Vec<F> {
+    let mut trace_record = populate_fp_operands_trace_record(op, cnt, left, [0.0; 2], res);
+    let cols: &mut ProgExecCols<F> = trace_record.as_mut_slice().borrow_mut();
+
+    for lane in 0..LANES {
+        populate_fp_bits(&mut cols.fp_lane_mut(lane).left_bits, left[lane].to_bits());
+    }
+
+    trace_record
+}
+
+#[cfg(test)]
+mod tests {
+    use p3_baby_bear::BabyBear;
+    use p3_field::{AbstractField, PrimeField64};
+
+    use super::*;
+    use crate::debug_builder::{count_failures, row, row_mut, run_program, test_regs};
+    use crate::math_ops::{
+        cfround_op, fadd_op, fdiv_op, fmul_op, fscal_op, fsqrt_op, fsub_op, fswap_op, I64MathOp,
+    };
+    use crate::prog_exec::{PV_FINAL_STATE, PV_INIT_STATE, STATE_SIZE};
+
+    fn fp_ops() -> Vec<I64MathOp<BabyBear>> {
+        vec![
+            fadd_op(),
+            fsub_op(),
+            fmul_op(),
+            fdiv_op(),
+            fsqrt_op(),
+            fscal_op(),
+            fswap_op(2),
+            fswap_op(5),
+            cfround_op(33),
+            fadd_op(),
+            fmul_op(),
+            fdiv_op(),
+            fsqrt_op(),
+            cfround_op(17),
+            fsub_op(),
+        ]
+    }
+
+    // r0 sets the rounding modes of CFRound
+    #[test]
+    fn fp_ops_satisfy_constraints() {
+        for r0 in [0, 0x3_0000_0002, -1, 0x0123_4567_89ab_cdef] {
+            let (prog, trace, public_values) = run_program(fp_ops(), test_regs(r0, 7), 3);
+            assert_eq!(
+                count_failures(&prog, &trace, &public_values),
+                0,
+                "r0 {}",
+                r0
+            );
+        }
+    }
+
+    fn limbs_to_u64(limbs: &[BabyBear; 8]) -> u64 {
+        (0..8).fold(0, |z, j| z | limbs[j].as_canonical_u64() << (8 * j))
+    }
+
+    // The FAdd of the first lane is 2 + 2^-52, a tie between 2 and the next double, so
+    // rounding up gives another result than to nearest. The whole run is moved to another
+    // mode, only the rounding of the FAdd is wrong.
+    #[test]
+    fn fadd_rejects_other_rounding_modes() {
+        let mut regs = test_regs(0, 7);
+        regs.f_regs[0][0] = 1.0;
+        regs.a_regs[1][0] = 1.0 + f64::EPSILON;
+        let (left, right) = (regs.f_regs[0][0], regs.a_regs[1][0]);
+        let (prog, trace, public_values) = run_program(vec![fadd_op()], regs, 1);
+        assert_eq!(count_failures(&prog, &trace, &public_values), 0);
+        let nearest = fp_compute(I64MathOps::FAdd, left, right, 0);
+        assert_eq!(limbs_to_u64(&row(&trace, 1).result), nearest.to_bits());
+
+        let mut differs = false;
+        for fprc in 1..4 {
+            let rounded = fp_compute(I64MathOps::FAdd, left, right, fprc);
+            let mut tampered = trace.clone();
+            let mut tampered_values = public_values.clone();
+            for r in 0..tampered.height() {
+                let cols = row_mut(&mut tampered, r);
+                cols.fprc_sel[0] = BabyBear::zero();
+                cols.fprc_sel[fprc as usize] = BabyBear::one();
+            }
+            for pv in [PV_INIT_STATE, PV_FINAL_STATE] {
+                tampered_values[pv + STATE_SIZE - 1] = BabyBear::from_canonical_u32(fprc);
+            }
+            if rounded != nearest {
+                assert!(
+                    count_failures(&prog, &tampered, &tampered_values) > 0,
+                    "fprc {}",
+                    fprc
+                );
+                differs = true;
+            }
+        }
+        assert!(differs);
+    }
+
+    // the sign of the double in the limbs and in its unpacked bits
+    fn flip_sign(limbs: &mut [BabyBear; 8], bits: &mut [BabyBear; 16]) {
+        let sign = BabyBear::from_canonical_u32(128);
+        if bits[15] == BabyBear::zero() {
+            limbs[7] += sign;
+        } else {
+            limbs[7] -= sign;
+        }
+        bits[15] = BabyBear::one() - bits[15];
+    }
+
+    #[test]
+    fn fsqrt_rejects_tampered_rows() {
+        let (prog, trace, public_values) = run_program(vec![fsqrt_op()], test_regs(0, 7), 1);
+        assert_eq!(count_failures(&prog, &trace, &public_values), 0);
+        let res = limbs_to_u64(&row(&trace, 1).result);
+        assert_ne!(res & 0xff, 0xff);
+
+        // the root one ULP above the rounded one
+        let mut tampered = trace.clone();
+        row_mut(&mut tampered, 1).result[0] += BabyBear::one();
+        assert!(count_failures(&prog, &tampered, &public_values) > 0);
+
+        // a negative input, the root of a positive one
+        let mut tampered = trace.clone();
+        let cols = row_mut(&mut tampered, 1);
+        let mut bits = cols.fp_lane(0).left_bits;
+        flip_sign(&mut cols.left_arg, &mut bits);
+        cols.fp_lane_mut(0).left_bits = bits;
+        assert!(count_failures(&prog, &tampered, &public_values) > 0);
+    }
+
+    #[test]
+    fn fadd_rejects_tampered_rows() {
+        let (prog, trace, public_values) = run_program(vec![fadd_op()], test_regs(0, 7), 1);
+        assert_eq!(count_failures(&prog, &trace, &public_values), 0);
+
+        // a result limb in each lane
+        for lane in 0..2 {
+            let mut tampered = trace.clone();
+            let cols = row_mut(&mut tampered, 1);
+            if lane == 0 {
+                cols.result[3] += BabyBear::one();
+            } else {
+                cols.result_hi[3] += BabyBear::one();
+            }
+            assert!(count_failures(&prog, &tampered, &public_values) > 0);
+        }
+
+        // the result sign
+        let mut tampered = trace.clone();
+        let cols = row_mut(&mut tampered, 1);
+        let mut bits = cols.fp_lane(0).res_bits;
+        flip_sign(&mut cols.result, &mut bits);
+        cols.fp_lane_mut(0).res_bits = bits;
+        assert!(count_failures(&prog, &tampered, &public_values) > 0);
+    }
+}
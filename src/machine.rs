@@ -0,0 +1,139 @@
+use hashbrown::HashMap;
+use p3_air::{Air, BaseAir, PairBuilder};
+use p3_field::{AbstractField, Field, PrimeField32};
+use p3_matrix::dense::RowMajorMatrix;
+use sp1_stark::air::{MachineAir, SP1AirBuilder};
+use sp1_stark::{Chip, MachineRecord};
+
+use crate::prog_exec::{ProgExec, ProgExecChip};
+use crate::program_table::ProgramChip;
+use crate::range_check::ByteChip;
+use crate::stark_primitives::BIN_OP_ROW_SIZE;
+
+// The trace of a program run and its public values, the chips generate their traces
+// from it. exec holds the ProgExec rows.
+#[derive(Clone, Debug, Default)]
+pub struct ProgExecRecord<F> {
+    pub exec: Vec<F>,
+    pub prog_len: usize,
+    pub public_values: Vec<F>,
+}
+
+impl<F: Field> ProgExecRecord<F> {
+    pub fn new(prog: &ProgExec<F>, trace: RowMajorMatrix<F>, public_values: Vec<F>) -> Self {
+        Self {
+            exec: trace.values,
+            prog_len: prog.ops.len(),
+            public_values,
+        }
+    }
+}
+
+impl<F: PrimeField32> MachineRecord for ProgExecRecord<F> {
+    type Config = ();
+
+    fn stats(&self) -> HashMap<String, usize> {
+        let mut stats = HashMap::new();
+        stats.insert("exec_rows".to_string(), self.exec.len() / BIN_OP_ROW_SIZE);
+        stats
+    }
+
+    fn append(&mut self, other: &mut Self) {
+        self.exec.append(&mut other.exec);
+    }
+
+    fn public_values<T: AbstractField>(&self) -> Vec<T> {
+        self.public_values
+            .iter()
+            .map(|v| T::from_canonical_u32(v.as_canonical_u32()))
+            .collect()
+    }
+}
+
+// The chips of the machine proving a program run. The program chip commits to the
+// program in the verifying key, the other chips do not depend on it.
+pub enum RandomXAir {
+    ProgExec(ProgExecChip),
+    Program(ProgramChip),
+    Byte(ByteChip),
+}
+
+impl RandomXAir {
+    pub fn get_all() -> Vec<Self> {
+        vec![
+            RandomXAir::ProgExec(ProgExecChip),
+            RandomXAir::Program(ProgramChip),
+            RandomXAir::Byte(ByteChip),
+        ]
+    }
+
+    pub fn chips<F: PrimeField32>() -> Vec<Chip<F, Self>> {
+        Self::get_all().into_iter().map(Chip::new).collect()
+    }
+}
+
+impl<F: Field> BaseAir<F> for RandomXAir {
+    fn width(&self) -> usize {
+        match self {
+            RandomXAir::ProgExec(chip) => BaseAir::<F>::width(chip),
+            RandomXAir::Program(chip) => BaseAir::<F>::width(chip),
+            RandomXAir::Byte(chip) => BaseAir::<F>::width(chip),
+        }
+    }
+}
+
+impl<F: PrimeField32> MachineAir<F> for RandomXAir {
+    type Record = ProgExecRecord<F>;
+
+    type Program = ProgExec<F>;
+
+    fn name(&self) -> String {
+        match self {
+            RandomXAir::ProgExec(chip) => MachineAir::<F>::name(chip),
+            RandomXAir::Program(chip) => MachineAir::<F>::name(chip),
+            RandomXAir::Byte(chip) => MachineAir::<F>::name(chip),
+        }
+    }
+
+    fn generate_trace(&self, input: &Self::Record, output: &mut Self::Record) -> RowMajorMatrix<F> {
+        match self {
+            RandomXAir::ProgExec(chip) => chip.generate_trace(input, output),
+            RandomXAir::Program(chip) => chip.generate_trace(input, output),
+            RandomXAir::Byte(chip) => chip.generate_trace(input, output),
+        }
+    }
+
+    fn included(&self, shard: &Self::Record) -> bool {
+        match self {
+            RandomXAir::ProgExec(chip) => chip.included(shard),
+            RandomXAir::Program(chip) => chip.included(shard),
+            RandomXAir::Byte(chip) => chip.included(shard),
+        }
+    }
+
+    fn preprocessed_width(&self) -> usize {
+        match self {
+            RandomXAir::ProgExec(chip) => MachineAir::<F>::preprocessed_width(chip),
+            RandomXAir::Program(chip) => MachineAir::<F>::preprocessed_width(chip),
+            RandomXAir::Byte(chip) => MachineAir::<F>::preprocessed_width(chip),
+        }
+    }
+
+    fn generate_preprocessed_trace(&self, program: &ProgExec<F>) -> Option<RowMajorMatrix<F>> {
+        match self {
+            RandomXAir::ProgExec(chip) => chip.generate_preprocessed_trace(program),
+            RandomXAir::Program(chip) => chip.generate_preprocessed_trace(program),
+            RandomXAir::Byte(chip) => chip.generate_preprocessed_trace(program),
+        }
+    }
+}
+
+impl<AB: SP1AirBuilder + PairBuilder> Air<AB> for RandomXAir {
+    fn eval(&self, builder: &mut AB) {
+        match self {
+            RandomXAir::ProgExec(chip) => chip.eval(builder),
+            RandomXAir::Program(chip) => chip.eval(builder),
+            RandomXAir::Byte(chip) => chip.eval(builder),
+        }
+    }
+}
@@ -1,37 +1,38 @@
+#[cfg(test)]
+mod debug_builder;
+mod fp_ops;
+mod machine;
 mod math_ops;
 mod prog_exec;
+mod program_table;
+mod range_check;
 mod register;
+mod scratchpad;
 mod stark_primitives;
 
-use hashbrown::HashMap;
-use p3_field::extension::BinomialExtensionField;
-use sp1_primitives::consts::WORD_SIZE;
-use sp1_recursion_core::air::{RecursionPublicValues, PV_DIGEST_NUM_WORDS, RECURSIVE_PROOF_NUM_PV_ELTS};
-// use serde::de;
 use sp1_sdk::SP1PublicValues;
-use sp1_stark::air::SP1_PROOF_NUM_PV_ELTS;
-use sp1_stark::baby_bear_poseidon2::{default_fri_config, BabyBearPoseidon2};
-// use std::fs::File;
+use sp1_stark::baby_bear_poseidon2::BabyBearPoseidon2;
 
 use clap::Parser;
-use math_ops::{add_op, sub_op};
-use p3_baby_bear::BabyBear;
-use p3_field::AbstractField;
+use machine::{ProgExecRecord, RandomXAir};
+use math_ops::{
+    add_op, add_rs_op, cbranch_op, cfround_op, fadd_op, fdiv_op, fmul_op, fscal_op, fsqrt_op,
+    fsub_op, fswap_op, istore_op, mul_op, mul_rcp_op, mulh_op, neg_op, rol_op, ror_op, smulh_op,
+    sub_op, swap_op, xor_op,
+};
 use sp1_prover::components::DefaultProverComponents;
-use sp1_prover::{SP1CoreProofData, SP1Prover};
+use sp1_prover::SP1Prover;
 use sp1_stark::{
-    inner_perm, AirOpenedValues, BabyBearPoseidon2Inner, Chip, ChipOpenedValues, InnerChallenger, MachineProof, SP1ProverOpts, ShardCommitment, ShardOpenedValues, ShardProof, StarkMachine, StarkVerifyingKey, Word, PROOF_MAX_NUM_PVS
+    CpuProver, MachineProver, MachineVerificationError, SP1ProverOpts, StarkGenericConfig,
+    StarkMachine,
 };
-use std::borrow::BorrowMut;
-
-// use std::io::Write;
 
-use p3_uni_stark::{get_log_quotient_degree, prove, verify, OpenedValues, VerificationError};
 use prog_exec::{
-    dummy_32b_public_values, dummy_public_values_hash, generate_program_trace, to_field_values,
-    ProgExec,
+    dummy_32b_public_values, dummy_public_values_hash, generate_program_trace,
+    generate_public_values, ProgExec, NUM_PUBLIC_VALUES,
 };
 use register::init_regs;
+use scratchpad::ScratchpadLevel;
 
 use tracing_forest::util::LevelFilter;
 use tracing_forest::ForestLayer;
@@ -39,162 +40,6 @@ use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::{EnvFilter, Registry};
 
-use stark_primitives::{InnerBabyBearPoseidon2, P3Proof, BIN_OP_ROW_SIZE};
-
-// type BabyBearExtentionField = ExtensionField<BabyBear>;
-use p3_field::ExtensionField;
-
-fn dummy_vk() -> StarkVerifyingKey<BabyBearPoseidon2> {
-    let chips = vec![
-        ("Byte".to_string(), 16),
-        ("MemoryProgram".to_string(), 14),
-        ("Program".to_string(), 14),
-        ("AddSub".to_string(), 4),
-        ("CPU".to_string(), 4),
-        ("MemoryLocal".to_string(), 4),
-    ];
-
-    let chip_ordering = chips
-        .iter()
-        .enumerate()
-        .map(|(i, (name, _))| (name.to_owned(), i))
-        .collect::<HashMap<_, _>>();
-
-    StarkVerifyingKey {
-        commit: [BabyBear::zero(); sp1_stark::DIGEST_SIZE].into(),
-        pc_start: BabyBear::zero(),
-        chip_information: vec![],
-        chip_ordering: chip_ordering,
-    }
-}
-
-fn convert_opened_values_<F: p3_field::Field, EF: ExtensionField<F>>(
-    // chip: &Chip<F, A>,
-    p3_opended_values: &OpenedValues<EF>,
-    log_degree: usize,
-) -> ChipOpenedValues<EF> {
-    // dummy values for pre and perm
-    // let preprocessed_width = chip.preprocessed_width();
-    // pre, main, perm must be unused
-    let preprocessed = AirOpenedValues {
-        local: vec![EF::zero(); BIN_OP_ROW_SIZE],
-        next: vec![EF::zero(); BIN_OP_ROW_SIZE],
-    };
-    // let main_width = chip.width();
-    // let permutation_width = chip.permutation_width();
-    let permutation = AirOpenedValues {
-        local: vec![EF::zero(); BIN_OP_ROW_SIZE * EF::D],
-        next: vec![EF::zero(); BIN_OP_ROW_SIZE * EF::D],
-    };
-
-    let OpenedValues {
-        trace_local,
-        trace_next,
-        quotient_chunks,
-    } = p3_opended_values;
-    // Put everything into main b/c main opnening values are handed over to
-    // pcs::verify
-    let main = AirOpenedValues {
-        local: trace_local.clone(),
-        next: trace_next.clone(),
-    };
-
-    // let quotient_width = chip.quotient_width();
-    let quotient = quotient_chunks.clone();
-
-    ChipOpenedValues {
-        preprocessed,
-        main,
-        permutation,
-        quotient,
-        global_cumulative_sum: EF::zero(),
-        local_cumulative_sum: EF::zero(),
-        log_degree,
-    }
-}
-
-// fn p3_proof_to_shardproof<SC: sp1_stark::StarkGenericConfig>(
-fn p3_proof_to_shardproof(
-    p3_proof: P3Proof,
-    public_values: Vec<BabyBear>, // must be [BabyBear; 32]
-    // air: ProgExec<BabyBear>,
-) -> ShardProof<sp1_stark::baby_bear_poseidon2::BabyBearPoseidon2> {
-    // let shape = ProofShape { chip_information: vec![("p3_stark".to_string(), 42)] };
-    let P3Proof {
-        commitments,
-        opened_values,
-        opening_proof,
-        degree_bits,
-    } = p3_proof;
-
-    let chip_opened_values = convert_opened_values_::<BabyBear, BinomialExtensionField<BabyBear, 4>>(
-        &opened_values,
-        degree_bits,
-    );
-
-    println!(
-        "chip_opened_values.main.local.len() {} {}",
-        chip_opened_values.main.local.len(),
-        chip_opened_values.log_degree,
-    );
-
-    let lengths = chip_opened_values
-        .quotient
-        .iter()
-        .map(|x| x.len())
-        .collect::<Vec<_>>();
-
-    println!(
-        "chip_opened_values.quotinent.len() {} widths {:?}",
-        chip_opened_values.quotient.len(),
-        lengths,
-    );
-
-    println!(
-        "opening_proof.fri_proof.commit_phase_commits.len() 1 {}",
-        opening_proof.fri_proof.commit_phase_commits.len()
-    );
-    let mut recursion_public_values_stream = [BabyBear::zero(); RECURSIVE_PROOF_NUM_PV_ELTS];
-    let recursion_public_values: &mut RecursionPublicValues<_> =
-        recursion_public_values_stream.as_mut_slice().borrow_mut();
-    let mut commited_value_digest = [Word([BabyBear::zero(); WORD_SIZE]); PV_DIGEST_NUM_WORDS];
-    for (i, word) in public_values.chunks(WORD_SIZE).enumerate() {
-        commited_value_digest[i] = word.into_iter().map(|x| *x).collect();
-    }
-    recursion_public_values.committed_value_digest = commited_value_digest;
-    let public_values = recursion_public_values.into_iter().collect();
-    // let public_values_append_len = PROOF_MAX_NUM_PVS.max(public_values.len()) - public_values.len();
-    // let mut public_values = public_values;
-    // public_values.append(&mut vec![BabyBear::zero(); public_values_append_len]);
-
-    let shard_proof = ShardProof {
-        commitment: ShardCommitment {
-            global_main_commit: [BabyBear::zero(); sp1_stark::DIGEST_SIZE].into(),
-            local_main_commit: commitments.trace,
-            permutation_commit: [BabyBear::zero(); sp1_stark::DIGEST_SIZE].into(),
-            quotient_commit: commitments.quotient_chunks,
-        },
-        opened_values: ShardOpenedValues {
-            chips: vec![chip_opened_values],
-        },
-        opening_proof,
-        chip_ordering: HashMap::new(),
-        public_values,
-    };
-
-    shard_proof
-}
-
-fn get_sp1_core_proofdata(
-    p3_proof: P3Proof,
-    public_values: Vec<BabyBear>,
-    /*air: ProgExec<BabyBear>*/
-) -> SP1CoreProofData {
-    let shard_proof = p3_proof_to_shardproof(p3_proof, public_values);
-    let shard_proofs = vec![shard_proof];
-    SP1CoreProofData(shard_proofs)
-}
-
 #[derive(Parser)]
 pub struct Cli {
     #[arg(short, long, default_value_t = 1)]
@@ -207,7 +52,7 @@ pub struct Cli {
     recursive: bool,
 }
 
-fn main() -> Result<(), VerificationError> {
+fn main() -> Result<(), MachineVerificationError<BabyBearPoseidon2>> {
     let cli = Cli::parse();
 
     let env_filter = EnvFilter::builder()
@@ -219,8 +64,45 @@ fn main() -> Result<(), VerificationError> {
         .with(ForestLayer::default())
         .init();
 
-    let add_op = add_op();
-    let sub_op = sub_op();
+    let program = [
+        add_op(),
+        sub_op(),
+        mul_op(),
+        mulh_op(),
+        smulh_op(),
+        mul_rcp_op(3_000_000_019),
+        xor_op(),
+        ror_op(),
+        rol_op(),
+        neg_op(),
+        swap_op(),
+        add_rs_op(3, 0xc0de_0042),
+        sub_op().with_imm(0xffff_fff0),
+        mul_op().with_imm(0x8000_0003),
+        xor_op().with_imm(0x7fff_1234),
+        rol_op().with_imm(13),
+        cbranch_op(3, 0x5a5a_0101, 0),
+        fadd_op(),
+        fsub_op(),
+        fmul_op(),
+        fdiv_op(),
+        fsqrt_op(),
+        fscal_op(),
+        fswap_op(1),
+        fswap_op(4),
+        cfround_op(17),
+        fadd_op(),
+        fmul_op(),
+        istore_op(ScratchpadLevel::L1, 0x3ff8),
+        istore_op(ScratchpadLevel::L3, 0xfff0_0010),
+        istore_op(ScratchpadLevel::L1, 0x3ff8),
+        add_op().with_mem(ScratchpadLevel::L1, 0x3ff8),
+        smulh_op().with_mem(ScratchpadLevel::L2, 0x8000_0010),
+        xor_op().with_mem(ScratchpadLevel::L3, 0x1234_5678),
+        fdiv_op()
+            .with_mem(ScratchpadLevel::L1, 0x3ff8)
+            .with_e_mask([0x3f00_0000_0012_3456, 0x3e00_0000_0020_0001]),
+    ];
 
     let regs_num = 2;
     let mut regs = init_regs(regs_num);
@@ -232,11 +114,7 @@ fn main() -> Result<(), VerificationError> {
     for _ in 0..1 {
         for _ in 0..1 {
             for i in 0..256 {
-                if i % 2 == 0 {
-                    ops.push(add_op);
-                } else {
-                    ops.push(sub_op);
-                }
+                ops.push(program[i % program.len()]);
             }
         }
     }
@@ -245,70 +123,47 @@ fn main() -> Result<(), VerificationError> {
     let global_nonce = dummy_32b_public_values(42);
     let local_nonce = dummy_32b_public_values(43);
     let mut hash_value = dummy_32b_public_values(44);
-    let mut prox_exec = ProgExec {
-        ops,
-        regs,
-        global_nonce,
-        local_nonce,
-        hash_value,
-    };
-
-    let trace = generate_program_trace(&mut prox_exec, &cli);
-
-    let perm = inner_perm();
-    let mut challenger = InnerChallenger::new(perm.clone());
-    let inner = BabyBearPoseidon2Inner::default();
-    let config = InnerBabyBearPoseidon2::new(inner.pcs);
+    let prox_exec = ProgExec::new(ops, regs, global_nonce, local_nonce, hash_value);
 
+    let (trace, final_regs) = generate_program_trace(&prox_exec, &cli);
     let public_values = dummy_public_values_hash(&global_nonce, &local_nonce, &hash_value);
-    let public_values_as_field = to_field_values(&public_values);
-    let p3_proof = prove(
-        &config,
-        &prox_exec,
-        &mut challenger,
-        trace,
-        &public_values_as_field,
-    );
-
-    let mut challenger = InnerChallenger::new(perm.clone());
-    verify(
-        &config,
-        &prox_exec,
-        &mut challenger,
-        &p3_proof,
-        &public_values_as_field,
-    )?;
-
-    // println!(
-    //     "p3_proof {}",
-    //     serde_json::to_string_pretty(&p3_proof).unwrap()
-    // );
+    let public_values_as_field = generate_public_values(&prox_exec, &final_regs, &trace);
+    let record = ProgExecRecord::new(&prox_exec, trace, public_values_as_field);
 
-    let log_quotient_degree = get_log_quotient_degree(&prox_exec, 0, 0);
-    println!("main log_quotient_degree {}", log_quotient_degree);
-    // Need to reduce a number of chips created down to 1
-    // log_quotinent_degree is 4 for recursive and 1 for non-recursive
-    let chip = Chip::new_(prox_exec, log_quotient_degree);
-    let chips = vec![chip];
-    let machine: StarkMachine<BabyBearPoseidon2, ProgExec<BabyBear>> = StarkMachine::new(
+    let machine = StarkMachine::new(
         BabyBearPoseidon2::new(),
-        chips,
-        SP1_PROOF_NUM_PV_ELTS,
+        RandomXAir::chips(),
+        NUM_PUBLIC_VALUES,
         false,
     );
+    let machine_prover = CpuProver::new(machine);
+    // the verifying key commits to the program table
+    let (pk, vk) = machine_prover.setup(&prox_exec);
+
+    let mut challenger = machine_prover.config().challenger();
+    let proof = machine_prover
+        .prove(&pk, vec![record], &mut challenger, ())
+        .expect("the program run must be provable");
+
+    let mut challenger = machine_prover.config().challenger();
+    machine_prover
+        .machine()
+        .verify(&vk, &proof, &mut challenger)?;
 
     let prover = SP1Prover::<DefaultProverComponents>::new();
     let opts = SP1ProverOpts::default();
 
     if cli.recursive {
-        let shard_proof = p3_proof_to_shardproof(p3_proof, public_values_as_field);
+        let shard_proof = proof.shard_proofs[0].clone();
         println!(
             "main shard_proof.public_values {}",
             serde_json::to_string(&shard_proof.public_values).unwrap(),
         );
         // println!("public_values length {}", shard_proof.public_values.len());
 
-        let outer_proof = prover.wrap_bn254_(shard_proof, opts, &machine).unwrap();
+        let outer_proof = prover
+            .wrap_bn254_(shard_proof, opts, machine_prover.machine())
+            .unwrap();
 
         println!("recursive after wrap_bn254_");
         println!(
@@ -356,22 +211,6 @@ fn main() -> Result<(), VerificationError> {
                 &groth16_bn254_artifacts,
             )
             .unwrap();
-    } else {
-        //     let mut public_values = dummy_public_values_hash(&global_nonce, &local_nonce, &hash_value);
-        // public_values[0] = 234;
-        // let public_values_as_field = to_field_values(&public_values);
-        let core_proofdata = get_sp1_core_proofdata(p3_proof, public_values_as_field);
-        let vk: StarkVerifyingKey<BabyBearPoseidon2> = dummy_vk();
-
-        let machine_proof = MachineProof {
-            shard_proofs: core_proofdata.0.to_vec(),
-        };
-        let mut challenger = InnerChallenger::new(perm.clone());
-        let chip = &machine.chips()[0];
-        machine
-            .verify_(&vk, &machine_proof, chip, &mut challenger)
-            .expect("Chip verification result must be Ok");
-        // prover.verify_(&core_proofdata,  &chip, &sp1vk).unwrap();
     }
 
     Ok(())
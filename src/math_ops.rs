@@ -1,60 +1,194 @@
-use std::borrow::Borrow;
-use std::{marker::PhantomData, ops::Neg};
+use std::borrow::{Borrow, BorrowMut};
+use std::marker::PhantomData;
 
 use p3_air::{Air, AirBuilder, AirBuilderWithPublicValues, BaseAir};
 use p3_field::AbstractField;
 use p3_field::Field;
 use p3_matrix::Matrix;
+use sp1_stark::air::{AirInteraction, InteractionScope, SP1AirBuilder};
+use sp1_stark::InteractionKind;
 
 use crate::{
-    register::RegFile,
-    stark_primitives::{BIN_OP_ROW_SIZE, CARRY, CARRY_START, LEFT_ARG, RESULT, RIGHT_ARG},
+    fp_ops::{
+        eval_fadd, eval_fdiv, eval_fdiv_mem, eval_fmul, eval_fscal, eval_fsqrt, eval_fswap,
+        fdiv_mem_operand, fp_compute, is_e_mask, populate_fadd_trace_record, populate_fdiv_mem,
+        populate_fdiv_trace_record, populate_fmul_trace_record, populate_fp_operands_trace_record,
+        populate_fscal_trace_record, populate_fsqrt_trace_record, E_MASK_EXP, FSCAL_MASK,
+    },
+    range_check::ByteOpcode,
+    register::{populate_reg_file, populate_reg_sel, RegFile},
+    scratchpad::{eval_mem_address, mem_address, pack_value, populate_mem_access, ScratchpadLevel},
+    stark_primitives::{ProgExecCols, BIN_OP_ROW_SIZE, CARRY, NUM_FLAGS, RCP_POW_BITS},
 };
 
-pub struct MathOpFirstRow<F: Field> {
-    pub cnt: F,
-    // the size must be 10 but this would prohibit 32 bytes public values hash
-    pub flags: [F; LEFT_ARG - 2],
-    pub public_values_sha256: [F; 32],
+// The first row overlays the public values hash on the operand columns
+#[repr(C)]
+pub struct MathOpFirstRow<T> {
+    pub cnt: T,
+    pub flags: [T; NUM_FLAGS],
+    pub public_values_sha256: [T; 32],
+    pub rest: [T; BIN_OP_ROW_SIZE - 1 - NUM_FLAGS - 32],
 }
 
 impl<F: Field> MathOpFirstRow<F> {
     // for generation of the first row
-    pub fn new(public_values_sha256: [F; 32]) -> MathOpFirstRow<F> {
-        let cnt = F::zero();
-        let flags = [F::zero(); LEFT_ARG - 2];
-        MathOpFirstRow {
-            cnt,
-            flags,
-            public_values_sha256,
-        }
+    pub fn populate(public_values_sha256: [F; 32]) -> Vec<F> {
+        let mut v = vec![F::zero(); BIN_OP_ROW_SIZE];
+        let first_row: &mut MathOpFirstRow<F> = v.as_mut_slice().borrow_mut();
+        first_row.public_values_sha256 = public_values_sha256;
+        v
     }
+}
 
-    pub fn consume_as_vec(&mut self) -> Vec<F> {
-        let mut v = Vec::with_capacity(BIN_OP_ROW_SIZE);
-        v.push(self.cnt);
-        v.extend_from_slice(&self.flags);
-        v.extend_from_slice(&self.public_values_sha256);
-        v
+impl<T> Borrow<MathOpFirstRow<T>> for [T] {
+    fn borrow(&self) -> &MathOpFirstRow<T> {
+        debug_assert_eq!(self.len(), BIN_OP_ROW_SIZE);
+        let (prefix, shorts, suffix) = unsafe { self.align_to::<MathOpFirstRow<T>>() };
+        debug_assert!(prefix.is_empty(), "Alignment should match");
+        debug_assert!(suffix.is_empty(), "Alignment should match");
+        debug_assert_eq!(shorts.len(), 1);
+        &shorts[0]
     }
 }
 
-// impl<F> Borrow<MathOpFirstRow<F>> for [F] {
-//     fn borrow(&self) -> &MathOpFirstRow<F> {
-//         debug_assert_eq!(self.len(), BIN_OP_ROW_SIZE);
-//         let (prefix, shorts, suffix) = unsafe { self.align_to::<MathOpFirstRow<F>>() };
-//         debug_assert!(prefix.is_empty(), "Alignment should match");
-//         debug_assert!(suffix.is_empty(), "Alignment should match");
-//         debug_assert_eq!(shorts.len(), 1);
-//         &shorts[0]
-//     }
-// }
+impl<T> BorrowMut<MathOpFirstRow<T>> for [T] {
+    fn borrow_mut(&mut self) -> &mut MathOpFirstRow<T> {
+        debug_assert_eq!(self.len(), BIN_OP_ROW_SIZE);
+        let (prefix, shorts, suffix) = unsafe { self.align_to_mut::<MathOpFirstRow<T>>() };
+        debug_assert!(prefix.is_empty(), "Alignment should match");
+        debug_assert!(suffix.is_empty(), "Alignment should match");
+        debug_assert_eq!(shorts.len(), 1);
+        &mut shorts[0]
+    }
+}
 
 #[derive(Clone, Copy, Debug)]
 pub enum I64MathOps {
     Add,
     Sub,
     NoOp,
+    Mul,
+    MulH,
+    SMulH,
+    MulRcp,
+    Xor,
+    Ror,
+    Rol,
+    Neg,
+    Swap,
+    AddRs,
+    CBranch,
+    FAdd,
+    FSub,
+    FMul,
+    FDiv,
+    FSqrt,
+    FScal,
+    FSwap,
+    CFRound,
+    IStore,
+}
+
+// every op in the order of the flags columns
+pub const ALL_OPS: [I64MathOps; 23] = [
+    I64MathOps::Add,
+    I64MathOps::Sub,
+    I64MathOps::NoOp,
+    I64MathOps::Mul,
+    I64MathOps::MulH,
+    I64MathOps::SMulH,
+    I64MathOps::MulRcp,
+    I64MathOps::Xor,
+    I64MathOps::Ror,
+    I64MathOps::Rol,
+    I64MathOps::Neg,
+    I64MathOps::Swap,
+    I64MathOps::AddRs,
+    I64MathOps::CBranch,
+    I64MathOps::FAdd,
+    I64MathOps::FSub,
+    I64MathOps::FMul,
+    I64MathOps::FDiv,
+    I64MathOps::FSqrt,
+    I64MathOps::FScal,
+    I64MathOps::FSwap,
+    I64MathOps::CFRound,
+    I64MathOps::IStore,
+];
+
+// the ops flags are the first NUM_OPS flags columns, the rest are unused
+pub const NUM_OPS: usize = ALL_OPS.len();
+const _: () = assert!(NUM_OPS <= NUM_FLAGS);
+
+impl I64MathOps {
+    // position of the op flag among the flags columns
+    pub fn flag_idx(&self) -> usize {
+        match self {
+            I64MathOps::Add => 0,
+            I64MathOps::Sub => 1,
+            I64MathOps::NoOp => 2,
+            I64MathOps::Mul => 3,
+            I64MathOps::MulH => 4,
+            I64MathOps::SMulH => 5,
+            I64MathOps::MulRcp => 6,
+            I64MathOps::Xor => 7,
+            I64MathOps::Ror => 8,
+            I64MathOps::Rol => 9,
+            I64MathOps::Neg => 10,
+            I64MathOps::Swap => 11,
+            I64MathOps::AddRs => 12,
+            I64MathOps::CBranch => 13,
+            I64MathOps::FAdd => 14,
+            I64MathOps::FSub => 15,
+            I64MathOps::FMul => 16,
+            I64MathOps::FDiv => 17,
+            I64MathOps::FSqrt => 18,
+            I64MathOps::FScal => 19,
+            I64MathOps::FSwap => 20,
+            I64MathOps::CFRound => 21,
+            I64MathOps::IStore => 22,
+        }
+    }
+}
+
+// ops that take the sign extended imm32 instead of the src register value
+// if the src register is the dst register
+pub const IMM_OPERAND_OPS: [I64MathOps; 6] = [
+    I64MathOps::Add,
+    I64MathOps::Sub,
+    I64MathOps::Mul,
+    I64MathOps::Xor,
+    I64MathOps::Ror,
+    I64MathOps::Rol,
+];
+
+// ops that have a form taking the right operand from the scratchpad
+pub const MEM_OPERAND_OPS: [I64MathOps; 7] = [
+    I64MathOps::Add,
+    I64MathOps::Sub,
+    I64MathOps::Mul,
+    I64MathOps::MulH,
+    I64MathOps::SMulH,
+    I64MathOps::Xor,
+    I64MathOps::FDiv,
+];
+
+impl I64MathOps {
+    pub fn has_imm_operand(&self) -> bool {
+        IMM_OPERAND_OPS
+            .iter()
+            .any(|op| op.flag_idx() == self.flag_idx())
+    }
+
+    pub fn has_mem_operand(&self) -> bool {
+        MEM_OPERAND_OPS
+            .iter()
+            .any(|op| op.flag_idx() == self.flag_idx())
+    }
+}
+
+pub fn flag<T: Copy>(local: &ProgExecCols<T>, op: I64MathOps) -> T {
+    local.flags[op.flag_idx()]
 }
 
 impl Default for I64MathOps {
@@ -71,6 +205,19 @@ pub struct I64MathOp<T> {
     pub left_reg_idx: u8,
     pub right_reg_idx: u8,
     pub res_reg_idx: u8,
+    pub imm32: u32,
+    // left shift of the right operand
+    pub shift: u8,
+    // CBranch: the masked bits start at cond + 8
+    pub cond: u8,
+    // CBranch: pc of the next op if the branch is taken
+    pub target: u32,
+    // the right operand is loaded from the scratchpad
+    pub mem_operand: bool,
+    // IStore and memory operands: the scratchpad level masking the address
+    pub mem_level: ScratchpadLevel,
+    // FDiv memory operand: the E masks of the divisor lanes
+    pub e_mask: [u64; 2],
     pub _u: PhantomData<T>,
 }
 
@@ -80,101 +227,704 @@ impl<F: Field> BaseAir<F> for I64MathOp<F> {
     }
 }
 
-pub fn eval_add<AB: AirBuilder + AirBuilderWithPublicValues>(builder: &mut AB, is_real: AB::Expr) {
-    let main = builder.main();
-    let local = main.row_slice(0);
-    // let is_real = local[1] + local[2];
-    // let next = main.row_slice(1);
-
+// Asserts left + right = res where left, right and res are the 8 limbs columns offsets.
+// Sub is proved as res + right = left.
+pub fn eval_add<AB: AirBuilder + AirBuilderWithPublicValues>(
+    builder: &mut AB,
+    is_real: AB::Expr,
+    left: &[AB::Var; 8],
+    right: &[AB::Var; 8],
+    res: &[AB::Var; 8],
+    carry: &[AB::Var; CARRY],
+) {
     let base = AB::F::from_canonical_u32(256);
     let one = AB::F::one();
     let mut when_is_real = builder.when(is_real.clone());
 
     when_is_real.assert_bool(is_real.clone());
-    // left = local[11..19];
-    // right = local[19..27];
-    // res = local[27..35];
-    // carry = local[35..42];
-
-    // For each limb, assert that difference between the carried result and the non-carried
-    // result is either zero or the base.
-    let overflow_0 = local[LEFT_ARG] + local[RIGHT_ARG] - local[RESULT];
-    let overflow_1 =
-        local[LEFT_ARG + 1] + local[RIGHT_ARG + 1] - local[RESULT + 1] + local[CARRY_START];
-    let overflow_2 =
-        local[LEFT_ARG + 2] + local[RIGHT_ARG + 2] - local[RESULT + 2] + local[CARRY_START + 1];
-    let overflow_3 =
-        local[LEFT_ARG + 3] + local[RIGHT_ARG + 3] - local[RESULT + 3] + local[CARRY_START + 2];
-    let overflow_4 =
-        local[LEFT_ARG + 4] + local[RIGHT_ARG + 4] - local[RESULT + 4] + local[CARRY_START + 3];
-    let overflow_5 =
-        local[LEFT_ARG + 5] + local[RIGHT_ARG + 5] - local[RESULT + 5] + local[CARRY_START + 4];
-    let overflow_6 =
-        local[LEFT_ARG + 6] + local[RIGHT_ARG + 6] - local[RESULT + 6] + local[CARRY_START + 5];
-    let overflow_7 =
-        local[LEFT_ARG + 7] + local[RIGHT_ARG + 7] - local[RESULT + 7] + local[CARRY_START + 6];
-
-    when_is_real.assert_zero(overflow_0.clone() * (overflow_0.clone() - base));
-    when_is_real.assert_zero(overflow_1.clone() * (overflow_1.clone() - base));
-    when_is_real.assert_zero(overflow_2.clone() * (overflow_2.clone() - base));
-    when_is_real.assert_zero(overflow_3.clone() * (overflow_3.clone() - base));
-    when_is_real.assert_zero(overflow_4.clone() * (overflow_4.clone() - base));
-    when_is_real.assert_zero(overflow_5.clone() * (overflow_5.clone() - base));
-    when_is_real.assert_zero(overflow_6.clone() * (overflow_6.clone() - base));
-    when_is_real.assert_zero(overflow_7.clone() * (overflow_7.clone() - base));
-
-    // If the carry is one, then the overflow must be the base.
-    when_is_real.assert_zero(local[CARRY_START] * (overflow_0.clone() - base));
-    when_is_real.assert_zero(local[CARRY_START + 1] * (overflow_1.clone() - base));
-    when_is_real.assert_zero(local[CARRY_START + 2] * (overflow_2.clone() - base));
-    when_is_real.assert_zero(local[CARRY_START + 3] * (overflow_3.clone() - base));
-    when_is_real.assert_zero(local[CARRY_START + 4] * (overflow_4.clone() - base));
-    when_is_real.assert_zero(local[CARRY_START + 5] * (overflow_5.clone() - base));
-    when_is_real.assert_zero(local[CARRY_START + 6] * (overflow_6.clone() - base));
-
-    // If the carry is not one, then the overflow must be zero.
-    when_is_real.assert_zero((local[CARRY_START] - one.clone()) * overflow_0.clone());
-    when_is_real.assert_zero((local[CARRY_START + 1] - one.clone()) * overflow_1.clone());
-    when_is_real.assert_zero((local[CARRY_START + 2] - one.clone()) * overflow_2.clone());
-    when_is_real.assert_zero((local[CARRY_START + 3] - one.clone()) * overflow_3.clone());
-    when_is_real.assert_zero((local[CARRY_START + 4] - one.clone()) * overflow_4.clone());
-    when_is_real.assert_zero((local[CARRY_START + 5] - one.clone()) * overflow_5.clone());
-    when_is_real.assert_zero((local[CARRY_START + 6] - one.clone()) * overflow_6.clone());
-
-    // Assert that the carry is either zero or one.
-    when_is_real.assert_bool(local[CARRY_START]);
-    when_is_real.assert_bool(local[CARRY_START + 1]);
-    when_is_real.assert_bool(local[CARRY_START + 2]);
-    when_is_real.assert_bool(local[CARRY_START + 3]);
-    when_is_real.assert_bool(local[CARRY_START + 4]);
-    when_is_real.assert_bool(local[CARRY_START + 5]);
-    when_is_real.assert_bool(local[CARRY_START + 6]);
-}
-
-impl<AB: AirBuilder + AirBuilderWithPublicValues> Air<AB> for I64MathOp<AB::F> {
-    fn eval(&self, builder: &mut AB) {
-        let main = builder.main();
-        let local = main.row_slice(0);
 
-        eval_add(builder, local[1] + local[2]);
-        // eval_add(builder, local[2]);
+    for i in 0..8 {
+        // For each limb, assert that difference between the carried result and the non-carried
+        // result is either zero or the base.
+        let mut overflow = left[i] + right[i] - res[i];
+        if i > 0 {
+            overflow = overflow + carry[i - 1];
+        }
+        when_is_real.assert_zero(overflow.clone() * (overflow.clone() - base));
+
+        if i < CARRY {
+            // If the carry is one, then the overflow must be the base.
+            when_is_real.assert_zero(carry[i] * (overflow.clone() - base));
+            // If the carry is not one, then the overflow must be zero.
+            when_is_real.assert_zero((carry[i] - one) * overflow);
+            // Assert that the carry is either zero or one.
+            when_is_real.assert_bool(carry[i]);
+        }
+    }
+}
+
+// 64-bit multiplications. The unsigned 128-bit product is laid out as 16 limbs:
+// for every limb k the sum of the partial products left[i] * right[k - i] plus
+// the previous carry must be equal to product[k] + 256 * carry[k].
+// Mul takes the low half of the product, MulH takes the high half and SMulH
+// corrects the high half with the operands signs:
+// signed_high = high - left_sign * right - right_sign * left (mod 2^64).
+pub fn eval_mul<AB: AirBuilder + AirBuilderWithPublicValues>(
+    builder: &mut AB,
+    local: &ProgExecCols<AB::Var>,
+    is_mul: AB::Expr,
+    is_mulh: AB::Expr,
+    is_smulh: AB::Expr,
+) {
+    let mul = local.mul();
+
+    let base = AB::F::from_canonical_u32(256);
+    let is_real = is_mul.clone() + is_mulh.clone() + is_smulh.clone();
+
+    let mut prev_carry = AB::Expr::zero();
+    for k in 0..16 {
+        let mut limb_sum = prev_carry;
+        for i in 0..8 {
+            if k >= i && k - i < 8 {
+                limb_sum = limb_sum + local.left_arg[i] * local.right_arg[k - i];
+            }
+        }
+        let carry = mul.carry[2 * k] + mul.carry[2 * k + 1] * base;
+
+        builder
+            .when(is_real.clone())
+            .assert_eq(limb_sum, mul.product[k] + carry.clone() * base);
+        prev_carry = carry;
+    }
+    // The product of two 64-bit values always fits into 128 bits.
+    builder.when(is_real).assert_zero(prev_carry);
+
+    for k in 0..8 {
+        builder
+            .when(is_mul.clone())
+            .assert_eq(local.result[k], mul.product[k]);
+        builder
+            .when(is_mulh.clone())
+            .assert_eq(local.result[k], mul.product[8 + k]);
+    }
+
+    let left_sign = mul.left_sign;
+    let right_sign = mul.right_sign;
+    let sign_base = AB::F::from_canonical_u32(128);
+    let mut when_smulh = builder.when(is_smulh);
+
+    when_smulh.assert_bool(left_sign);
+    when_smulh.assert_bool(right_sign);
+    when_smulh.assert_eq(local.left_arg[7], left_sign * sign_base + mul.left_low7);
+    when_smulh.assert_eq(local.right_arg[7], right_sign * sign_base + mul.right_low7);
+
+    // res + left_sign * right + right_sign * left = high (mod 2^64)
+    let mut prev_carry = AB::Expr::zero();
+    for k in 0..8 {
+        let limb_sum = local.result[k]
+            + left_sign * local.right_arg[k]
+            + right_sign * local.left_arg[k]
+            + prev_carry;
+
+        when_smulh.assert_eq(limb_sum, mul.product[8 + k] + mul.smulh_carry[k] * base);
+        prev_carry = mul.smulh_carry[k].into();
     }
 }
 
-pub fn populate_flags<F: Field>(op: I64MathOps) -> Vec<F> {
-    let mut flags = vec![F::zero(); 10];
-    match op {
-        I64MathOps::Add => {
-            flags[0] = F::one();
+// IMUL_RCP multiplies by the reciprocal rcp = 2^e / imm32 that is supplied by the prover
+// as the right operand. The reciprocal is the floor quotient iff imm32 * rcp + rem = 2^e
+// with rem < imm32, and rcp >= 2^63 makes e the bit length of imm32 plus 63.
+pub fn eval_mul_rcp<AB: AirBuilder + AirBuilderWithPublicValues>(
+    builder: &mut AB,
+    local: &ProgExecCols<AB::Var>,
+    is_real: AB::Expr,
+) {
+    let mul = local.mul();
+
+    let base = AB::F::from_canonical_u32(256);
+    let mut when_is_real = builder.when(is_real);
+
+    when_is_real.assert_eq(
+        local.right_arg[7],
+        mul.rcp_top + AB::F::from_canonical_u32(128),
+    );
+
+    // 2^e limbs out of the one-hot encoded e
+    let mut pow_sum = AB::Expr::zero();
+    let mut pow_limbs = vec![AB::Expr::zero(); 12];
+    for i in 0..RCP_POW_BITS {
+        let bit = 63 + i;
+        when_is_real.assert_bool(mul.rcp_pow[i]);
+        pow_sum = pow_sum + mul.rcp_pow[i];
+        pow_limbs[bit / 8] =
+            pow_limbs[bit / 8].clone() + mul.rcp_pow[i] * AB::F::from_canonical_u32(1 << (bit % 8));
+    }
+    when_is_real.assert_one(pow_sum);
+
+    // imm32 * rcp + rem = 2^e
+    let mut prev_carry = AB::Expr::zero();
+    for k in 0..12 {
+        let mut limb_sum = prev_carry;
+        for i in 0..4 {
+            if k >= i && k - i < 8 {
+                limb_sum = limb_sum + local.imm[i] * local.right_arg[k - i];
+            }
         }
-        I64MathOps::Sub => {
-            flags[1] = F::one();
+        if k < 4 {
+            limb_sum = limb_sum + mul.rcp_rem[k];
+        }
+        let carry = mul.rcp_carry[2 * k] + mul.rcp_carry[2 * k + 1] * base;
+
+        when_is_real.assert_eq(limb_sum, pow_limbs[k].clone() + carry.clone() * base);
+        prev_carry = carry;
+    }
+    when_is_real.assert_zero(prev_carry);
+
+    // rem + diff + 1 = imm32, so rem < imm32
+    let mut prev_carry = AB::Expr::one();
+    for k in 0..4 {
+        let limb_sum = mul.rcp_rem[k] + mul.rcp_rem_diff[k] + prev_carry.clone();
+        if k < 3 {
+            when_is_real.assert_bool(mul.rcp_rem_carry[k]);
+            when_is_real.assert_eq(limb_sum, local.imm[k] + mul.rcp_rem_carry[k] * base);
+            prev_carry = mul.rcp_rem_carry[k].into();
+        } else {
+            when_is_real.assert_eq(limb_sum, local.imm[k]);
+        }
+    }
+}
+
+// Byte-wise XOR. Every operands and result limbs triple is looked up in the byte table.
+pub fn eval_xor<AB: SP1AirBuilder>(
+    builder: &mut AB,
+    local: &ProgExecCols<AB::Var>,
+    is_real: AB::Expr,
+) {
+    for i in 0..8 {
+        let values = vec![
+            AB::Expr::from_canonical_u8(ByteOpcode::Xor as u8),
+            local.left_arg[i].into(),
+            local.right_arg[i].into(),
+            local.result[i].into(),
+        ];
+        builder.send(
+            AirInteraction::new(values, is_real.clone(), InteractionKind::Byte),
+            InteractionScope::Local,
+        );
+    }
+}
+
+// 64-bit rotations by right & 63. Rol by s is evaluated as Ror by (64 - s) & 63.
+// The rotation by e = 8 * k + m bits first rotates the left operand by k bytes
+// and then every byte y is split so that y * 2^(8 - m) = 256 * hi + lo,
+// the result limb is hi of the byte plus lo of the next byte.
+pub fn eval_rotate<AB: AirBuilder + AirBuilderWithPublicValues>(
+    builder: &mut AB,
+    local: &ProgExecCols<AB::Var>,
+    is_ror: AB::Expr,
+    is_rol: AB::Expr,
+) {
+    let rot = local.rotate();
+
+    let base = AB::F::from_canonical_u32(256);
+    let is_real = is_ror.clone() + is_rol.clone();
+
+    // shift = right & 63
+    let mut right_0 = AB::Expr::zero();
+    let mut shift = AB::Expr::zero();
+    for j in 0..8 {
+        let bit = rot.shift_bits[j];
+        builder.when(is_real.clone()).assert_bool(bit);
+        right_0 = right_0 + bit * AB::F::from_canonical_u32(1 << j);
+        if j < 6 {
+            shift = shift + bit * AB::F::from_canonical_u32(1 << j);
+        }
+    }
+    builder
+        .when(is_real.clone())
+        .assert_eq(local.right_arg[0], right_0);
+
+    // rotation amount e = 8 * k + m and 2^(8 - m)
+    let mut byte_sel_sum = AB::Expr::zero();
+    let mut bit_sel_sum = AB::Expr::zero();
+    let mut rotation = AB::Expr::zero();
+    let mut pow = AB::Expr::zero();
+    for k in 0..8 {
+        let byte_sel = rot.byte_sel[k];
+        let bit_sel = rot.bit_sel[k];
+        builder.when(is_real.clone()).assert_bool(byte_sel);
+        builder.when(is_real.clone()).assert_bool(bit_sel);
+
+        byte_sel_sum = byte_sel_sum + byte_sel;
+        bit_sel_sum = bit_sel_sum + bit_sel;
+        rotation = rotation
+            + byte_sel * AB::F::from_canonical_u32(8 * k as u32)
+            + bit_sel * AB::F::from_canonical_u32(k as u32);
+        pow = pow + bit_sel * AB::F::from_canonical_u32(1 << (8 - k));
+    }
+    builder.when(is_real.clone()).assert_one(byte_sel_sum);
+    builder.when(is_real.clone()).assert_one(bit_sel_sum);
+
+    builder
+        .when(is_ror)
+        .assert_eq(rotation.clone(), shift.clone());
+    let mut when_rol = builder.when(is_rol);
+    when_rol.assert_bool(rot.wrap);
+    when_rol.assert_eq(rotation + shift, rot.wrap * AB::F::from_canonical_u32(64));
+
+    let mut when_is_real = builder.when(is_real);
+    for i in 0..8 {
+        let mut rotated = AB::Expr::zero();
+        for k in 0..8 {
+            rotated = rotated + rot.byte_sel[k] * local.left_arg[(i + k) % 8];
+        }
+        when_is_real.assert_eq(rot.bytes[i], rotated);
+        when_is_real.assert_eq(rot.bytes[i] * pow.clone(), rot.hi[i] * base + rot.lo[i]);
+        when_is_real.assert_eq(local.result[i], rot.hi[i] + rot.lo[(i + 1) % 8]);
+    }
+}
+
+// Padding rows are inert: no operands, no result and no taken branch.
+pub fn eval_noop<AB: AirBuilder + AirBuilderWithPublicValues>(
+    builder: &mut AB,
+    local: &ProgExecCols<AB::Var>,
+    is_padding: AB::Expr,
+) {
+    let mut when_is_padding = builder.when(is_padding);
+
+    for i in 0..8 {
+        when_is_padding.assert_zero(local.left_arg[i]);
+        when_is_padding.assert_zero(local.right_arg[i]);
+        when_is_padding.assert_zero(local.result[i]);
+        when_is_padding.assert_zero(local.left_arg_hi[i]);
+        when_is_padding.assert_zero(local.right_arg_hi[i]);
+        when_is_padding.assert_zero(local.result_hi[i]);
+    }
+    for i in 0..CARRY {
+        when_is_padding.assert_zero(local.carry[i]);
+    }
+    for i in 0..4 {
+        when_is_padding.assert_zero(local.imm[i]);
+    }
+    when_is_padding.assert_zero(local.taken);
+}
+
+// Two's complement negation: left + res = 0 (mod 2^64). Every limb sum is either
+// zero or the base and the carries are bits, the top limb carry is discarded.
+pub fn eval_neg<AB: AirBuilder + AirBuilderWithPublicValues>(
+    builder: &mut AB,
+    local: &ProgExecCols<AB::Var>,
+    is_real: AB::Expr,
+) {
+    let base = AB::F::from_canonical_u32(256);
+    let mut when_is_real = builder.when(is_real);
+
+    let mut prev_carry = AB::Expr::zero();
+    for i in 0..8 {
+        let limb_sum = local.left_arg[i] + local.result[i] + prev_carry.clone();
+        if i < CARRY {
+            when_is_real.assert_bool(local.carry[i]);
+            when_is_real.assert_eq(limb_sum, local.carry[i] * base);
+            prev_carry = local.carry[i].into();
+        } else {
+            when_is_real.assert_zero(limb_sum.clone() * (limb_sum - base));
+        }
+    }
+}
+
+// CFRound rotates the left operand right by imm32 with the Ror constraints,
+// the new rounding mode is the 2 low bits of the rotated value
+pub fn eval_cfround<AB: AirBuilder + AirBuilderWithPublicValues>(
+    builder: &mut AB,
+    local: &ProgExecCols<AB::Var>,
+    is_real: AB::Expr,
+) {
+    let rot = local.rotate();
+
+    let mut when_is_real = builder.when(is_real);
+    when_is_real.assert_eq(local.right_arg[0], local.imm[0]);
+
+    let mut res_0 = AB::Expr::zero();
+    for j in 0..8 {
+        when_is_real.assert_bool(rot.cfr_bits[j]);
+        res_0 = res_0 + rot.cfr_bits[j] * AB::F::from_canonical_u32(1 << j);
+    }
+    when_is_real.assert_eq(local.result[0], res_0);
+}
+
+// IStore writes the src register value to base + imm32 masked by the scratchpad level
+pub fn eval_istore<AB: AirBuilder + AirBuilderWithPublicValues>(
+    builder: &mut AB,
+    local: &ProgExecCols<AB::Var>,
+    is_real: AB::Expr,
+) {
+    let base: Vec<AB::Expr> = (0..3).map(|i| local.left_arg[i].into()).collect();
+    eval_mem_address(builder, local, is_real.clone(), &base);
+
+    let value = pack_value::<AB>(&local.src_val);
+    let mut when_is_real = builder.when(is_real);
+    for (k, packed) in value.into_iter().enumerate() {
+        when_is_real.assert_eq(local.mem_val[k], packed);
+    }
+}
+
+// Memory operand ops load the right operand from src + imm32 masked by the scratchpad
+// level. The address is imm32 masked by L3 if the src and dst registers are the same.
+// FDiv loads the int32 pair of its divisor, see eval_fdiv_mem.
+pub fn eval_mem_operand<AB: AirBuilder + AirBuilderWithPublicValues>(
+    builder: &mut AB,
+    local: &ProgExecCols<AB::Var>,
+) {
+    let is_real = local.mem_operand;
+    let mem_ops = MEM_OPERAND_OPS
+        .iter()
+        .fold(AB::Expr::zero(), |acc, op| acc + flag(local, *op));
+    builder.assert_zero(is_real * (AB::Expr::one() - mem_ops));
+    // FDiv converts the loaded value to its divisor, its integer src is never its E group dst
+    let is_fp_mem = local.fp_mem_operand;
+    builder.assert_eq(is_fp_mem, is_real * flag(local, I64MathOps::FDiv));
+    builder.assert_zero(is_fp_mem * local.idx_eq);
+
+    let not_idx_eq = AB::Expr::one() - local.idx_eq;
+    let base: Vec<AB::Expr> = (0..3)
+        .map(|i| local.src_val[i] * not_idx_eq.clone())
+        .collect();
+    eval_mem_address(builder, local, is_real.into(), &base);
+
+    builder.when(is_real).assert_zero(
+        local.idx_eq * (AB::Expr::one() - local.mem_level_sel[ScratchpadLevel::L3.sel_idx()]),
+    );
+    let value = pack_value::<AB>(&local.right_arg);
+    let mut when_int_mem = builder.when(is_real - is_fp_mem);
+    for (k, packed) in value.into_iter().enumerate() {
+        when_int_mem.assert_eq(local.mem_val[k], packed);
+    }
+}
+
+// Exactly one op flag is set in every row but the first one, which holds the public values.
+// The unused flags are pinned to 0, so a row always runs the constraints of its op.
+pub fn eval_flags<AB: AirBuilder + AirBuilderWithPublicValues>(
+    builder: &mut AB,
+    local: &ProgExecCols<AB::Var>,
+) {
+    let mut flags_sum = AB::Expr::zero();
+    for i in 0..NUM_FLAGS {
+        let op_flag = local.flags[i];
+        if i < NUM_OPS {
+            builder.assert_bool(op_flag);
+            flags_sum = flags_sum + op_flag;
+        } else {
+            builder.assert_zero(op_flag);
+        }
+    }
+    let not_first_row = AB::Expr::one() - builder.is_first_row();
+    builder.assert_eq(flags_sum, not_first_row);
+}
+
+// IStore writes and memory operands read the scratchpad
+pub fn eval_mem_flags<AB: AirBuilder + AirBuilderWithPublicValues>(
+    builder: &mut AB,
+    local: &ProgExecCols<AB::Var>,
+) {
+    let is_store = flag(local, I64MathOps::IStore);
+    builder.assert_eq(local.mem_access, is_store + local.mem_operand);
+    builder.assert_eq(local.mem_write, is_store);
+}
+
+// the rounding mode set by CFRound
+pub fn cfround_mode<AB: AirBuilder>(local: &ProgExecCols<AB::Var>) -> AB::Expr {
+    let rot = local.rotate();
+    rot.cfr_bits[0] + rot.cfr_bits[1] * AB::F::two()
+}
+
+// Register exchange: res is written into the left operand register
+// and swap res is written into the right operand register.
+pub fn eval_swap<AB: AirBuilder + AirBuilderWithPublicValues>(
+    builder: &mut AB,
+    local: &ProgExecCols<AB::Var>,
+    is_real: AB::Expr,
+) {
+    let swap = local.swap();
+
+    let mut when_is_real = builder.when(is_real);
+
+    for i in 0..8 {
+        when_is_real.assert_eq(local.result[i], local.right_arg[i]);
+        when_is_real.assert_eq(swap.res[i], local.left_arg[i]);
+    }
+}
+
+// imm32 limbs sign extended to 64 bits
+pub fn imm_ext_limbs<AB: AirBuilder>(local: &ProgExecCols<AB::Var>) -> Vec<AB::Expr> {
+    let sign_ext = local.imm_sign * AB::F::from_canonical_u32(255);
+    (0..8)
+        .map(|i| {
+            if i < 4 {
+                local.imm[i].into()
+            } else {
+                sign_ext.clone()
+            }
+        })
+        .collect()
+}
+
+// the top imm32 limb is split into the sign bit and the 7 lower bits
+pub fn eval_imm_sign<AB: AirBuilder + AirBuilderWithPublicValues>(
+    builder: &mut AB,
+    local: &ProgExecCols<AB::Var>,
+    is_real: AB::Expr,
+) {
+    let mut when_is_real = builder.when(is_real);
+
+    when_is_real.assert_bool(local.imm_sign);
+    when_is_real.assert_eq(
+        local.imm[3],
+        local.imm_sign * AB::F::from_canonical_u32(128) + local.imm_low7,
+    );
+}
+
+// IADD_RS: res = left + (right << shift) + sign extended imm32. imm32 is zero
+// unless the displacement is used. The limb carries are bounded by 10
+// and the top limb carry is discarded.
+pub fn eval_add_rs<AB: AirBuilder + AirBuilderWithPublicValues>(
+    builder: &mut AB,
+    local: &ProgExecCols<AB::Var>,
+    is_real: AB::Expr,
+) {
+    let add_rs = local.add_rs();
+
+    let base = AB::F::from_canonical_u32(256);
+    let imm = imm_ext_limbs::<AB>(local);
+    let mut when_is_real = builder.when(is_real);
+
+    let mut shift_sel_sum = AB::Expr::zero();
+    let mut pow = AB::Expr::zero();
+    for j in 0..4 {
+        let shift_sel = add_rs.shift_sel[j];
+        when_is_real.assert_bool(shift_sel);
+        shift_sel_sum = shift_sel_sum + shift_sel;
+        pow = pow + shift_sel * AB::F::from_canonical_u32(1 << j);
+    }
+    when_is_real.assert_one(shift_sel_sum);
+
+    let mut prev_carry = AB::Expr::zero();
+    for i in 0..8 {
+        let limb_sum =
+            local.left_arg[i] + local.right_arg[i] * pow.clone() + imm[i].clone() + prev_carry;
+        let carry = if i < CARRY {
+            local.carry[i]
+        } else {
+            add_rs.carry_top
+        };
+
+        when_is_real.assert_eq(limb_sum, local.result[i] + carry * base);
+        prev_carry = carry.into();
+    }
+}
+
+// The right operand is either the src register value or the sign extended imm32.
+// The latter is used by IMM_OPERAND_OPS when the src and dst registers are the same.
+// Neither is used if the right operand is loaded from the scratchpad.
+pub fn eval_imm_operand<AB: AirBuilder + AirBuilderWithPublicValues>(
+    builder: &mut AB,
+    local: &ProgExecCols<AB::Var>,
+) {
+    let imm_ops = IMM_OPERAND_OPS
+        .iter()
+        .fold(AB::Expr::zero(), |acc, op| acc + flag(local, *op));
+    builder.assert_bool(local.mem_operand);
+    builder.assert_eq(
+        local.reg_operand,
+        imm_ops * (AB::Expr::one() - local.mem_operand),
+    );
+    let idx_diff = local.right_reg_idx - local.res_reg_idx;
+
+    // is-equal gadget, the integer memory operands also use it
+    let mut when_idx_used =
+        builder.when(local.reg_operand + local.mem_operand - local.fp_mem_operand);
+    when_idx_used.assert_zero(local.idx_eq * idx_diff.clone());
+    when_idx_used.assert_one(local.idx_eq + idx_diff * local.idx_diff_inv);
+
+    builder.assert_eq(local.use_imm, local.idx_eq * local.reg_operand);
+
+    let imm = imm_ext_limbs::<AB>(local);
+    let mut when_mux = builder.when(local.reg_operand);
+    for i in 0..8 {
+        when_mux.assert_eq(
+            local.right_arg[i],
+            local.src_val[i] + local.use_imm * (imm[i].clone() - local.src_val[i]),
+        );
+    }
+}
+
+// CBRANCH: res = left + sign extended imm32. The branch is taken if the 8 result bits
+// starting at cond + 8 are all zero, i.e. their sum is zero.
+pub fn eval_cbranch<AB: AirBuilder + AirBuilderWithPublicValues>(
+    builder: &mut AB,
+    local: &ProgExecCols<AB::Var>,
+    is_real: AB::Expr,
+) {
+    let cbr = local.cbranch();
+
+    let base = AB::F::from_canonical_u32(256);
+    let imm = imm_ext_limbs::<AB>(local);
+
+    builder.assert_zero((AB::Expr::one() - is_real.clone()) * local.taken);
+
+    let mut when_is_real = builder.when(is_real);
+
+    let mut prev_carry = AB::Expr::zero();
+    for i in 0..8 {
+        let carry = if i < CARRY {
+            local.carry[i]
+        } else {
+            cbr.carry_top
+        };
+        when_is_real.assert_bool(carry);
+        when_is_real.assert_eq(
+            local.left_arg[i] + imm[i].clone() + prev_carry,
+            local.result[i] + carry * base,
+        );
+        prev_carry = carry.into();
+    }
+
+    for i in 0..3 {
+        let mut limb = AB::Expr::zero();
+        for j in 0..8 {
+            let bit = cbr.bits[8 * i + j];
+            when_is_real.assert_bool(bit);
+            limb = limb + bit * AB::F::from_canonical_u32(1 << j);
         }
-        I64MathOps::NoOp => {
-            flags[2] = F::one();
+        when_is_real.assert_eq(local.result[1 + i], limb);
+    }
+
+    let mut cond_sel_sum = AB::Expr::zero();
+    let mut masked = AB::Expr::zero();
+    for k in 0..16 {
+        let cond_sel = cbr.cond_sel[k];
+        when_is_real.assert_bool(cond_sel);
+        cond_sel_sum = cond_sel_sum + cond_sel;
+
+        let mut bits_sum = AB::Expr::zero();
+        for j in 0..8 {
+            bits_sum = bits_sum + cbr.bits[k + j];
         }
+        masked = masked + cond_sel * bits_sum;
+    }
+    when_is_real.assert_one(cond_sel_sum);
+    when_is_real.assert_eq(cbr.masked, masked);
+
+    // is-zero gadget
+    when_is_real.assert_zero(cbr.masked * local.taken);
+    when_is_real.assert_one(local.taken + cbr.masked * cbr.masked_inv);
+}
+
+impl<AB: SP1AirBuilder + AirBuilderWithPublicValues> Air<AB> for I64MathOp<AB::F> {
+    fn eval(&self, builder: &mut AB) {
+        let main = builder.main();
+        let local = main.row_slice(0);
+        let local: &ProgExecCols<AB::Var> = (*local).borrow();
+
+        eval_flags(builder, local);
+        eval_imm_operand(builder, local);
+        eval_imm_sign(
+            builder,
+            local,
+            local.use_imm + flag(local, I64MathOps::AddRs) + flag(local, I64MathOps::CBranch),
+        );
+        eval_add(
+            builder,
+            flag(local, I64MathOps::Add).into(),
+            &local.left_arg,
+            &local.right_arg,
+            &local.result,
+            &local.carry,
+        );
+        eval_add(
+            builder,
+            flag(local, I64MathOps::Sub).into(),
+            &local.result,
+            &local.right_arg,
+            &local.left_arg,
+            &local.carry,
+        );
+        eval_mul(
+            builder,
+            local,
+            flag(local, I64MathOps::Mul) + flag(local, I64MathOps::MulRcp),
+            flag(local, I64MathOps::MulH).into(),
+            flag(local, I64MathOps::SMulH).into(),
+        );
+        eval_mul_rcp(builder, local, flag(local, I64MathOps::MulRcp).into());
+        eval_xor(builder, local, flag(local, I64MathOps::Xor).into());
+        eval_rotate(
+            builder,
+            local,
+            flag(local, I64MathOps::Ror) + flag(local, I64MathOps::CFRound),
+            flag(local, I64MathOps::Rol).into(),
+        );
+        eval_neg(builder, local, flag(local, I64MathOps::Neg).into());
+        eval_noop(builder, local, flag(local, I64MathOps::NoOp).into());
+        eval_swap(builder, local, flag(local, I64MathOps::Swap).into());
+        eval_add_rs(builder, local, flag(local, I64MathOps::AddRs).into());
+        eval_cbranch(builder, local, flag(local, I64MathOps::CBranch).into());
+        eval_fadd(
+            builder,
+            local,
+            flag(local, I64MathOps::FAdd).into(),
+            flag(local, I64MathOps::FSub).into(),
+        );
+        eval_fmul(builder, local, flag(local, I64MathOps::FMul).into());
+        eval_fdiv(builder, local, flag(local, I64MathOps::FDiv).into());
+        eval_fdiv_mem(builder, local);
+        eval_fsqrt(builder, local, flag(local, I64MathOps::FSqrt).into());
+        eval_fscal(builder, local, flag(local, I64MathOps::FScal).into());
+        eval_fswap(builder, local, flag(local, I64MathOps::FSwap).into());
+        eval_cfround(builder, local, flag(local, I64MathOps::CFRound).into());
+        eval_istore(builder, local, flag(local, I64MathOps::IStore).into());
+        eval_mem_operand(builder, local);
+        eval_mem_flags(builder, local);
     }
-    flags
+}
+
+// cnt, flags and operands limbs, the rest of the row is zeroed
+pub fn populate_operands_trace_record<F: Field>(
+    op: I64MathOps,
+    cnt: u32,
+    left: i64,
+    right: i64,
+    res: i64,
+) -> Vec<F> {
+    let mut trace_record = populate_noop_trace_record(op, cnt);
+    let cols: &mut ProgExecCols<F> = trace_record.as_mut_slice().borrow_mut();
+    cols.left_arg = left.to_le_bytes().map(F::from_canonical_u8);
+    cols.right_arg = right.to_le_bytes().map(F::from_canonical_u8);
+    cols.result = res.to_le_bytes().map(F::from_canonical_u8);
+    trace_record
+}
+
+pub fn populate_imm_operand<F: Field>(
+    cols: &mut ProgExecCols<F>,
+    right_idx: u8,
+    res_idx: u8,
+    src_is_dst: bool,
+    reg_operand: bool,
+    use_imm: bool,
+    imm32: u32,
+    src: i64,
+) {
+    let right_idx = F::from_canonical_u8(right_idx);
+    let res_idx = F::from_canonical_u8(res_idx);
+    cols.right_reg_idx = right_idx;
+    cols.res_reg_idx = res_idx;
+    cols.idx_eq = F::from_bool(src_is_dst);
+    cols.idx_diff_inv = (right_idx - res_idx).try_inverse().unwrap_or(F::zero());
+    cols.reg_operand = F::from_bool(reg_operand);
+    cols.use_imm = F::from_bool(use_imm);
+    if use_imm {
+        populate_imm(cols, imm32);
+    }
+    cols.src_val = src.to_le_bytes().map(F::from_canonical_u8);
 }
 
 pub fn populate_add_trace_record<F: Field>(
@@ -184,118 +934,1045 @@ pub fn populate_add_trace_record<F: Field>(
     right: i64,
     res: i64,
 ) -> Vec<F> {
-    let mut trace_record = Vec::with_capacity(BIN_OP_ROW_SIZE);
+    let mut trace_record = populate_operands_trace_record(op, cnt, left, right, res);
+    populate_add_carries(trace_record.as_mut_slice().borrow_mut(), left, right);
 
-    trace_record.push(F::from_canonical_u32(cnt));
-    let mut flags = populate_flags(op);
-    trace_record.append(&mut flags);
+    trace_record
+}
 
+// Sub row keeps the original right operand and the carries of res + right = left
+pub fn populate_sub_trace_record<F: Field>(
+    op: I64MathOps,
+    cnt: u32,
+    left: i64,
+    right: i64,
+    res: i64,
+) -> Vec<F> {
+    let mut trace_record = populate_operands_trace_record(op, cnt, left, right, res);
+    populate_add_carries(trace_record.as_mut_slice().borrow_mut(), res, right);
+
+    trace_record
+}
+
+pub fn populate_add_carries<F: Field>(cols: &mut ProgExecCols<F>, left: i64, right: i64) {
     let left_as_b = left.to_le_bytes();
     let right_as_b = right.to_le_bytes();
-    let res_as_b = res.to_le_bytes();
 
-    // println!("left_as_b: {:?}", left_as_b);
-    for el in left_as_b {
-        trace_record.push(F::from_canonical_u8(el));
+    let mut prev_carry_value = 0u8;
+    for i in 0..CARRY {
+        if (left_as_b[i] as u32) + (right_as_b[i] as u32) + (prev_carry_value as u32) > 255 {
+            cols.carry[i] = F::one();
+            prev_carry_value = 1;
+        } else {
+            prev_carry_value = 0;
+        };
     }
+}
 
-    // println!("right_as_b: {:?}", right_as_b);
-    for el in right_as_b {
-        trace_record.push(F::from_canonical_u8(el));
+pub fn populate_mul_trace_record<F: Field>(
+    op: I64MathOps,
+    cnt: u32,
+    left: i64,
+    right: i64,
+    res: i64,
+) -> Vec<F> {
+    let mut trace_record = populate_operands_trace_record(op, cnt, left, right, res);
+    let cols: &mut ProgExecCols<F> = trace_record.as_mut_slice().borrow_mut();
+    let mul = cols.mul_mut();
+
+    let left_as_b = left.to_le_bytes();
+    let right_as_b = right.to_le_bytes();
+    let product_as_b = ((left as u64 as u128) * (right as u64 as u128)).to_le_bytes();
+
+    let mut carry = 0u32;
+    for k in 0..16 {
+        let mut limb_sum = carry;
+        for i in 0..8 {
+            if k >= i && k - i < 8 {
+                limb_sum += (left_as_b[i] as u32) * (right_as_b[k - i] as u32);
+            }
+        }
+        carry = limb_sum >> 8;
+        mul.product[k] = F::from_canonical_u8(product_as_b[k]);
+        mul.carry[2 * k] = F::from_canonical_u32(carry & 0xff);
+        mul.carry[2 * k + 1] = F::from_canonical_u32(carry >> 8);
     }
 
-    for el in res_as_b {
-        trace_record.push(F::from_canonical_u8(el));
+    let left_sign = (left_as_b[7] >> 7) as u32;
+    let right_sign = (right_as_b[7] >> 7) as u32;
+    mul.left_sign = F::from_canonical_u32(left_sign);
+    mul.right_sign = F::from_canonical_u32(right_sign);
+    mul.left_low7 = F::from_canonical_u8(left_as_b[7] & 0x7f);
+    mul.right_low7 = F::from_canonical_u8(right_as_b[7] & 0x7f);
+
+    if let I64MathOps::SMulH = op {
+        let res_as_b = res.to_le_bytes();
+        let mut carry = 0u32;
+        for k in 0..8 {
+            let limb_sum = (res_as_b[k] as u32)
+                + left_sign * (right_as_b[k] as u32)
+                + right_sign * (left_as_b[k] as u32)
+                + carry;
+            carry = limb_sum >> 8;
+            mul.smulh_carry[k] = F::from_canonical_u32(carry);
+        }
     }
 
-    let mut prev_carry_value = 0u8;
+    trace_record
+}
+
+pub fn populate_imm<F: Field>(cols: &mut ProgExecCols<F>, imm32: u32) {
+    cols.imm = imm32.to_le_bytes().map(F::from_canonical_u8);
+    cols.imm_sign = F::from_canonical_u32(imm32 >> 31);
+    cols.imm_low7 = F::from_canonical_u32((imm32 >> 24) & 0x7f);
+}
+
+// 2^e / divisor where e is 63 plus the bit length of the divisor,
+// i.e. the largest power of 2 s.t. the quotient fits into 64 bits.
+pub fn reciprocal(divisor: u32) -> u64 {
+    let shift = 32 - divisor.leading_zeros();
+    ((1u128 << (63 + shift)) / divisor as u128) as u64
+}
+
+pub fn populate_mul_rcp_trace_record<F: Field>(
+    op: I64MathOps,
+    cnt: u32,
+    left: i64,
+    rcp: i64,
+    imm32: u32,
+    res: i64,
+) -> Vec<F> {
+    let mut trace_record = populate_mul_trace_record(op, cnt, left, rcp, res);
+    let cols: &mut ProgExecCols<F> = trace_record.as_mut_slice().borrow_mut();
+
+    let shift = (32 - imm32.leading_zeros()) as usize;
+    let rem = ((1u128 << (63 + shift)) % imm32 as u128) as u32;
+    let diff = imm32 - rem - 1;
+
+    let imm_as_b = imm32.to_le_bytes();
+    populate_imm(cols, imm32);
+    let mul = cols.mul_mut();
+
+    let rem_as_b = rem.to_le_bytes();
+    let diff_as_b = diff.to_le_bytes();
+    let rcp_as_b = rcp.to_le_bytes();
+
+    for i in 0..4 {
+        mul.rcp_rem[i] = F::from_canonical_u8(rem_as_b[i]);
+        mul.rcp_rem_diff[i] = F::from_canonical_u8(diff_as_b[i]);
+    }
+
+    let mut carry = 1u32;
+    for i in 0..3 {
+        carry = ((rem_as_b[i] as u32) + (diff_as_b[i] as u32) + carry) >> 8;
+        mul.rcp_rem_carry[i] = F::from_canonical_u32(carry);
+    }
+
+    mul.rcp_top = F::from_canonical_u8(rcp_as_b[7] & 0x7f);
+
+    let mut carry = 0u32;
+    for k in 0..12 {
+        let mut limb_sum = carry;
+        for i in 0..4 {
+            if k >= i && k - i < 8 {
+                limb_sum += (imm_as_b[i] as u32) * (rcp_as_b[k - i] as u32);
+            }
+        }
+        if k < 4 {
+            limb_sum += rem_as_b[k] as u32;
+        }
+        carry = limb_sum >> 8;
+        mul.rcp_carry[2 * k] = F::from_canonical_u32(carry & 0xff);
+        mul.rcp_carry[2 * k + 1] = F::from_canonical_u32(carry >> 8);
+    }
+
+    mul.rcp_pow[shift] = F::one();
+
+    trace_record
+}
+
+// the byte chip counts the limbs lookups
+pub fn populate_xor_trace_record<F: Field>(
+    op: I64MathOps,
+    cnt: u32,
+    left: i64,
+    right: i64,
+    res: i64,
+) -> Vec<F> {
+    populate_operands_trace_record(op, cnt, left, right, res)
+}
+
+// rotation is the right rotation amount in 0..64
+pub fn populate_rotate_trace_record<F: Field>(
+    op: I64MathOps,
+    cnt: u32,
+    left: i64,
+    right: i64,
+    rotation: u32,
+    res: i64,
+) -> Vec<F> {
+    let mut trace_record = populate_operands_trace_record(op, cnt, left, right, res);
+    let cols: &mut ProgExecCols<F> = trace_record.as_mut_slice().borrow_mut();
+    let rot = cols.rotate_mut();
+
+    let right_0 = right.to_le_bytes()[0];
+    for j in 0..8 {
+        rot.shift_bits[j] = F::from_canonical_u8((right_0 >> j) & 1);
+    }
+    if let I64MathOps::Rol = op {
+        if right_0 & 63 != 0 {
+            rot.wrap = F::one();
+        }
+    }
+
+    let byte_rotation = (rotation / 8) as usize;
+    let bit_rotation = rotation % 8;
+    rot.byte_sel[byte_rotation] = F::one();
+    rot.bit_sel[bit_rotation as usize] = F::one();
+
+    let rotated_as_b = (left as u64)
+        .rotate_right(8 * byte_rotation as u32)
+        .to_le_bytes();
+    for i in 0..8 {
+        let shifted = (rotated_as_b[i] as u32) << (8 - bit_rotation);
+        rot.bytes[i] = F::from_canonical_u8(rotated_as_b[i]);
+        rot.hi[i] = F::from_canonical_u32(shifted >> 8);
+        rot.lo[i] = F::from_canonical_u32(shifted & 0xff);
+    }
+
+    trace_record
+}
+
+pub fn populate_cfround_trace_record<F: Field>(
+    op: I64MathOps,
+    cnt: u32,
+    left: i64,
+    imm32: u32,
+    rotation: u32,
+    res: i64,
+) -> Vec<F> {
+    let mut trace_record = populate_rotate_trace_record(op, cnt, left, imm32 as i64, rotation, res);
+    let cols: &mut ProgExecCols<F> = trace_record.as_mut_slice().borrow_mut();
+    populate_imm(cols, imm32);
+
+    let res_0 = res.to_le_bytes()[0];
+    let rot = cols.rotate_mut();
+    for j in 0..8 {
+        rot.cfr_bits[j] = F::from_canonical_u8((res_0 >> j) & 1);
+    }
+
+    trace_record
+}
+
+pub fn populate_neg_trace_record<F: Field>(
+    op: I64MathOps,
+    cnt: u32,
+    left: i64,
+    res: i64,
+) -> Vec<F> {
+    let mut trace_record = populate_operands_trace_record(op, cnt, left, 0, res);
+    let cols: &mut ProgExecCols<F> = trace_record.as_mut_slice().borrow_mut();
+
+    let left_as_b = left.to_le_bytes();
+    let res_as_b = res.to_le_bytes();
+
+    let mut carry = 0u32;
     for i in 0..CARRY {
-        if (left_as_b[i] as u32) + (right_as_b[i] as u32) + (prev_carry_value as u32) > 255 {
-            trace_record.push(F::one());
-            prev_carry_value = 1;
+        carry = ((left_as_b[i] as u32) + (res_as_b[i] as u32) + carry) >> 8;
+        cols.carry[i] = F::from_canonical_u32(carry);
+    }
+
+    trace_record
+}
+
+pub fn populate_swap_trace_record<F: Field>(
+    op: I64MathOps,
+    cnt: u32,
+    left: i64,
+    right: i64,
+) -> Vec<F> {
+    let mut trace_record = populate_operands_trace_record(op, cnt, left, right, right);
+    let cols: &mut ProgExecCols<F> = trace_record.as_mut_slice().borrow_mut();
+
+    cols.swap_mut().res = left.to_le_bytes().map(F::from_canonical_u8);
+
+    trace_record
+}
+
+// imm32 is the displacement or zero if it is not used
+pub fn populate_add_rs_trace_record<F: Field>(
+    op: I64MathOps,
+    cnt: u32,
+    left: i64,
+    right: i64,
+    shift: u8,
+    imm32: u32,
+    res: i64,
+) -> Vec<F> {
+    let mut trace_record = populate_operands_trace_record(op, cnt, left, right, res);
+    let cols: &mut ProgExecCols<F> = trace_record.as_mut_slice().borrow_mut();
+    populate_imm(cols, imm32);
+    cols.add_rs_mut().shift_sel[shift as usize] = F::one();
+    cols.prog_shift = F::from_canonical_u8(shift);
+
+    let left_as_b = left.to_le_bytes();
+    let right_as_b = right.to_le_bytes();
+    let imm_as_b = (imm32 as i32 as i64).to_le_bytes();
+
+    let mut carry = 0u32;
+    for i in 0..8 {
+        carry = ((left_as_b[i] as u32)
+            + ((right_as_b[i] as u32) << shift)
+            + (imm_as_b[i] as u32)
+            + carry)
+            >> 8;
+        if i < CARRY {
+            cols.carry[i] = F::from_canonical_u32(carry);
         } else {
-            trace_record.push(F::zero());
-            prev_carry_value = 0;
-        };
+            cols.add_rs_mut().carry_top = F::from_canonical_u32(carry);
+        }
     }
 
     trace_record
 }
 
-pub fn populate_noop_trace_record<F: Field>(op: I64MathOps, cnt: u32) -> Vec<F> {
-    let mut trace_record = Vec::with_capacity(BIN_OP_ROW_SIZE);
+// imm32 is the one with the condition bit set and the bit below cleared
+pub fn populate_cbranch_trace_record<F: Field>(
+    op: I64MathOps,
+    cnt: u32,
+    left: i64,
+    imm32: u32,
+    res: i64,
+    cond: u8,
+    target: u32,
+) -> Vec<F> {
+    let mut trace_record = populate_operands_trace_record(op, cnt, left, 0, res);
+    let cols: &mut ProgExecCols<F> = trace_record.as_mut_slice().borrow_mut();
+    populate_imm(cols, imm32);
+    cols.prog_target = F::from_canonical_u32(target);
+    cols.prog_cond = F::from_canonical_u8(cond);
 
-    trace_record.push(F::from_canonical_u32(cnt));
-    let mut flags = populate_flags(op);
-    trace_record.append(&mut flags);
+    let left_as_b = left.to_le_bytes();
+    let imm_as_b = (imm32 as i32 as i64).to_le_bytes();
+    let mut carry = 0u32;
+    for i in 0..8 {
+        carry = (left_as_b[i] as u32 + imm_as_b[i] as u32 + carry) >> 8;
+        if i < CARRY {
+            cols.carry[i] = F::from_canonical_u32(carry);
+        } else {
+            cols.cbranch_mut().carry_top = F::from_canonical_u32(carry);
+        }
+    }
+
+    let masked_bits = (res as u64 >> (cond + 8)) & 0xff;
+    cols.taken = F::from_bool(masked_bits == 0);
+
+    let cbr = cols.cbranch_mut();
+    for i in 0..24 {
+        cbr.bits[i] = F::from_canonical_u64((res as u64 >> (8 + i)) & 1);
+    }
+    cbr.cond_sel[cond as usize] = F::one();
 
-    trace_record.append(&mut vec![F::zero(); BIN_OP_ROW_SIZE - LEFT_ARG]);
+    let masked = F::from_canonical_u32(masked_bits.count_ones());
+    cbr.masked = masked;
+    cbr.masked_inv = masked.try_inverse().unwrap_or(F::zero());
 
     trace_record
 }
 
+// base is the dst register value and the stored value is the src register one
+pub fn populate_istore_trace_record<F: Field>(
+    op: I64MathOps,
+    cnt: u32,
+    base: i64,
+    imm32: u32,
+    level: ScratchpadLevel,
+    addr: u32,
+    value: i64,
+) -> Vec<F> {
+    let mut trace_record = populate_operands_trace_record(op, cnt, base, 0, 0);
+    let cols: &mut ProgExecCols<F> = trace_record.as_mut_slice().borrow_mut();
+    populate_imm(cols, imm32);
+    populate_mem_access(cols, level, base as u64, imm32, addr, true, value as u64);
+
+    trace_record
+}
+
+// cnt and flags, the rest of the row is zeroed
+pub fn populate_noop_trace_record<F: Field>(op: I64MathOps, cnt: u32) -> Vec<F> {
+    let mut trace_record = vec![F::zero(); BIN_OP_ROW_SIZE];
+    let cols: &mut ProgExecCols<F> = trace_record.as_mut_slice().borrow_mut();
+    cols.cnt = F::from_canonical_u32(cnt);
+    cols.flags[op.flag_idx()] = F::one();
+    trace_record
+}
+
 impl<F: Field> I64MathOp<F> {
+    // the op on r0 with every other field zeroed, the constructors override what they use
+    pub fn base(op: I64MathOps) -> Self {
+        Self {
+            op,
+            left_arg: 0,
+            right_arg: 0,
+            left_reg_idx: 0,
+            right_reg_idx: 0,
+            res_reg_idx: 0,
+            imm32: 0,
+            shift: 0,
+            cond: 0,
+            target: 0,
+            mem_operand: false,
+            mem_level: ScratchpadLevel::L3,
+            e_mask: [E_MASK_EXP; 2],
+            _u: PhantomData,
+        }
+    }
+
+    // the src register is the dst register so the op takes imm32 as the right operand
+    pub fn with_imm(mut self, imm32: u32) -> Self {
+        self.right_reg_idx = self.res_reg_idx;
+        self.imm32 = imm32;
+        self
+    }
+
+    // MEM_OPERAND_OPS only: the right operand is loaded from src + imm32
+    // masked by the scratchpad level
+    pub fn with_mem(mut self, mem_level: ScratchpadLevel, imm32: u32) -> Self {
+        assert!(
+            self.op.has_mem_operand(),
+            "{:?} has no memory operand",
+            self.op
+        );
+        self.mem_operand = true;
+        self.mem_level = mem_level;
+        self.imm32 = imm32;
+        self
+    }
+
+    // FDiv memory operand only: the E masks of the divisor lanes
+    pub fn with_e_mask(mut self, e_mask: [u64; 2]) -> Self {
+        assert!(
+            matches!(self.op, I64MathOps::FDiv) && self.mem_operand,
+            "{:?} has no E masked memory operand",
+            self.op
+        );
+        assert!(e_mask.iter().all(|m| is_e_mask(*m)), "not an E mask");
+        self.e_mask = e_mask;
+        self
+    }
+
+    // the integer src register is the dst register, the FDiv dst is an E group register
+    pub fn src_is_dst(&self) -> bool {
+        self.right_reg_idx == self.res_reg_idx && !matches!(self.op, I64MathOps::FDiv)
+    }
+
+    // imm32 as the op takes it
+    pub fn op_imm32(&self) -> u32 {
+        match self.op {
+            // RandomX adds the displacement only if the destination is r5
+            I64MathOps::AddRs if self.res_reg_idx != 5 => 0,
+            // RandomX sets the condition bit and clears the one below it
+            I64MathOps::CBranch => {
+                let b = self.cond as u32 + 8;
+                (self.imm32 | (1 << b)) & !(1 << (b - 1))
+            }
+            _ => self.imm32,
+        }
+    }
+
     pub fn generate(&mut self, reg_file: &mut RegFile, _values: &mut Vec<F>) -> Vec<F> {
         let left_idx = self.left_reg_idx as usize;
         let right_idx = self.right_reg_idx as usize;
         let res_idx = self.res_reg_idx as usize;
 
+        let regs = reg_file.packed::<F>();
+        let src = reg_file.int_regs[right_idx];
+        let reg_operand = self.op.has_imm_operand() && !self.mem_operand;
+        let use_imm = reg_operand && self.src_is_dst();
         self.left_arg = reg_file.int_regs[left_idx];
-        self.right_arg = reg_file.int_regs[right_idx];
 
-        let trace_record = match self.op {
+        // the memory operand address is imm32 masked by L3 if src is dst
+        let mut mem_load = None;
+        self.right_arg = if self.mem_operand {
+            let (base, level) = if self.src_is_dst() {
+                (0, ScratchpadLevel::L3)
+            } else {
+                (src as u64, self.mem_level)
+            };
+            let addr = mem_address(base, self.imm32, level);
+            let value = reg_file.scratchpad.read(addr, reg_file.cnt);
+            mem_load = Some((base, level, addr, value));
+            value as i64
+        } else if use_imm {
+            self.imm32 as i32 as i64
+        } else {
+            src
+        };
+
+        let mut next_pc = reg_file.pc + 1;
+        let fprc = reg_file.fprc;
+
+        let mut trace_record = match self.op {
             I64MathOps::Add => {
                 let r = self.left_arg.wrapping_add(self.right_arg);
                 reg_file.int_regs[res_idx as usize] = r;
                 populate_add_trace_record(self.op, reg_file.cnt, self.left_arg, self.right_arg, r)
             }
             I64MathOps::Sub => {
-                let right_arg = self.right_arg.neg();
-                let r = self.left_arg.wrapping_add(right_arg);
+                let r = self.left_arg.wrapping_sub(self.right_arg);
+                reg_file.int_regs[res_idx as usize] = r;
+                populate_sub_trace_record(self.op, reg_file.cnt, self.left_arg, self.right_arg, r)
+            }
+            I64MathOps::Mul => {
+                let r = self.left_arg.wrapping_mul(self.right_arg);
+                reg_file.int_regs[res_idx as usize] = r;
+                populate_mul_trace_record(self.op, reg_file.cnt, self.left_arg, self.right_arg, r)
+            }
+            I64MathOps::MulH => {
+                let r = (((self.left_arg as u64 as u128) * (self.right_arg as u64 as u128)) >> 64)
+                    as i64;
                 reg_file.int_regs[res_idx as usize] = r;
-                populate_add_trace_record(self.op, reg_file.cnt, self.left_arg, right_arg, r)
+                populate_mul_trace_record(self.op, reg_file.cnt, self.left_arg, self.right_arg, r)
+            }
+            I64MathOps::SMulH => {
+                let r = (((self.left_arg as i128) * (self.right_arg as i128)) >> 64) as i64;
+                reg_file.int_regs[res_idx as usize] = r;
+                populate_mul_trace_record(self.op, reg_file.cnt, self.left_arg, self.right_arg, r)
+            }
+            I64MathOps::MulRcp => {
+                self.right_arg = reciprocal(self.imm32) as i64;
+                let r = self.left_arg.wrapping_mul(self.right_arg);
+                reg_file.int_regs[res_idx as usize] = r;
+                populate_mul_rcp_trace_record(
+                    self.op,
+                    reg_file.cnt,
+                    self.left_arg,
+                    self.right_arg,
+                    self.imm32,
+                    r,
+                )
+            }
+            I64MathOps::Xor => {
+                let r = self.left_arg ^ self.right_arg;
+                reg_file.int_regs[res_idx as usize] = r;
+                populate_xor_trace_record(self.op, reg_file.cnt, self.left_arg, self.right_arg, r)
+            }
+            I64MathOps::Ror | I64MathOps::Rol => {
+                let shift = (self.right_arg & 63) as u32;
+                let rotation = match self.op {
+                    I64MathOps::Ror => shift,
+                    _ => (64 - shift) & 63,
+                };
+                let r = (self.left_arg as u64).rotate_right(rotation) as i64;
+                reg_file.int_regs[res_idx as usize] = r;
+                populate_rotate_trace_record(
+                    self.op,
+                    reg_file.cnt,
+                    self.left_arg,
+                    self.right_arg,
+                    rotation,
+                    r,
+                )
+            }
+            I64MathOps::Neg => {
+                let r = self.left_arg.wrapping_neg();
+                reg_file.int_regs[res_idx as usize] = r;
+                populate_neg_trace_record(self.op, reg_file.cnt, self.left_arg, r)
+            }
+            I64MathOps::Swap => {
+                reg_file.int_regs[res_idx as usize] = self.right_arg;
+                reg_file.int_regs[right_idx as usize] = self.left_arg;
+                populate_swap_trace_record(self.op, reg_file.cnt, self.left_arg, self.right_arg)
+            }
+            I64MathOps::AddRs => {
+                let imm32 = self.op_imm32();
+                let r = self
+                    .left_arg
+                    .wrapping_add(self.right_arg << self.shift)
+                    .wrapping_add(imm32 as i32 as i64);
+                reg_file.int_regs[res_idx as usize] = r;
+                populate_add_rs_trace_record(
+                    self.op,
+                    reg_file.cnt,
+                    self.left_arg,
+                    self.right_arg,
+                    self.shift,
+                    imm32,
+                    r,
+                )
+            }
+            I64MathOps::CBranch => {
+                let b = self.cond as u32 + 8;
+                let imm32 = self.op_imm32();
+                let r = self.left_arg.wrapping_add(imm32 as i32 as i64);
+                reg_file.int_regs[res_idx as usize] = r;
+                let taken = (r as u64 >> b) & 0xff == 0;
+                if taken {
+                    next_pc = self.target;
+                }
+                populate_cbranch_trace_record(
+                    self.op,
+                    reg_file.cnt,
+                    self.left_arg,
+                    imm32,
+                    r,
+                    self.cond,
+                    self.target,
+                )
+            }
+            I64MathOps::FAdd | I64MathOps::FSub => {
+                // the F group register is updated with an A group register
+                let left = reg_file.f_regs[left_idx];
+                let right = reg_file.a_regs[right_idx];
+                let r = [0, 1].map(|i| fp_compute(self.op, left[i], right[i], fprc));
+                reg_file.f_regs[res_idx] = r;
+                populate_fadd_trace_record(self.op, reg_file.cnt, fprc, left, right, r)
+            }
+            I64MathOps::FMul => {
+                // the E group register is multiplied by an A group register
+                let left = reg_file.e_regs[left_idx];
+                let right = reg_file.a_regs[right_idx];
+                let r = [0, 1].map(|i| fp_compute(self.op, left[i], right[i], fprc));
+                reg_file.e_regs[res_idx] = r;
+                populate_fmul_trace_record(self.op, reg_file.cnt, fprc, left, right, r)
+            }
+            I64MathOps::FDiv => {
+                // the divisor is an A group register or the masked loaded int32 pair
+                let left = reg_file.e_regs[left_idx];
+                let right = if self.mem_operand {
+                    fdiv_mem_operand(self.right_arg as u64, self.e_mask)
+                } else {
+                    reg_file.a_regs[right_idx]
+                };
+                let r = [0, 1].map(|i| fp_compute(self.op, left[i], right[i], fprc));
+                reg_file.e_regs[res_idx] = r;
+                let mut trace_record =
+                    populate_fdiv_trace_record(self.op, reg_file.cnt, fprc, left, right, r);
+                if self.mem_operand {
+                    populate_fdiv_mem(
+                        trace_record.as_mut_slice().borrow_mut(),
+                        self.right_arg as u64,
+                        self.e_mask,
+                    );
+                }
+                trace_record
+            }
+            I64MathOps::FSqrt => {
+                let left = reg_file.e_regs[left_idx];
+                let r = [0, 1].map(|i| fp_compute(self.op, left[i], 0.0, fprc));
+                reg_file.e_regs[res_idx] = r;
+                populate_fsqrt_trace_record(self.op, reg_file.cnt, fprc, left, r)
+            }
+            I64MathOps::FScal => {
+                let left = reg_file.f_regs[left_idx];
+                let r = left.map(|x| f64::from_bits(x.to_bits() ^ FSCAL_MASK));
+                reg_file.f_regs[res_idx] = r;
+                populate_fscal_trace_record(self.op, reg_file.cnt, left, r)
+            }
+            I64MathOps::FSwap => {
+                // either an F or an E group register
+                let left = reg_file.fp_reg(left_idx);
+                let r = [left[1], left[0]];
+                reg_file.set_fp_reg(res_idx, r);
+                populate_fp_operands_trace_record(self.op, reg_file.cnt, left, [0.0; 2], r)
+            }
+            I64MathOps::CFRound => {
+                // the rounding mode is the rotated source register masked by 3
+                let rotation = self.imm32 & 63;
+                let r = (self.left_arg as u64).rotate_right(rotation) as i64;
+                reg_file.fprc = (r & 3) as u32;
+                populate_cfround_trace_record(
+                    self.op,
+                    reg_file.cnt,
+                    self.left_arg,
+                    self.imm32,
+                    rotation,
+                    r,
+                )
+            }
+            I64MathOps::IStore => {
+                let addr = mem_address(self.left_arg as u64, self.imm32, self.mem_level);
+                reg_file.scratchpad.write(addr, reg_file.cnt, src as u64);
+                populate_istore_trace_record(
+                    self.op,
+                    reg_file.cnt,
+                    self.left_arg,
+                    self.imm32,
+                    self.mem_level,
+                    addr,
+                    src,
+                )
             }
             I64MathOps::NoOp => populate_noop_trace_record(self.op, reg_file.cnt),
         };
+        let cols: &mut ProgExecCols<F> = trace_record.as_mut_slice().borrow_mut();
+        populate_imm_operand(
+            cols,
+            self.right_reg_idx,
+            self.res_reg_idx,
+            self.src_is_dst(),
+            reg_operand,
+            use_imm,
+            self.imm32,
+            src,
+        );
+        populate_reg_file(cols, &regs);
+        populate_reg_sel(
+            cols,
+            self.op,
+            left_idx,
+            right_idx,
+            res_idx,
+            self.mem_operand,
+        );
+        if let Some((base, level, addr, value)) = mem_load {
+            cols.mem_operand = F::one();
+            populate_imm(cols, self.imm32);
+            populate_mem_access(cols, level, base, self.imm32, addr, false, value);
+        }
+        cols.fprc_sel[fprc as usize] = F::one();
+        cols.is_real = F::from_bool(!matches!(self.op, I64MathOps::NoOp));
 
         reg_file.cnt += 1;
+        reg_file.pc = next_pc;
         // values.append(trace_record.as_mut());
         trace_record
     }
 }
 
 pub fn add_op<Val: Field>() -> I64MathOp<Val> {
-    I64MathOp::<Val> {
-        op: I64MathOps::Add,
-        left_arg: 0,
+    I64MathOp {
         right_arg: 1,
-        left_reg_idx: 0,
         right_reg_idx: 1,
-        res_reg_idx: 0,
-        _u: PhantomData,
+        ..I64MathOp::base(I64MathOps::Add)
     }
 }
 
 pub fn sub_op<Val: Field>() -> I64MathOp<Val> {
-    I64MathOp::<Val> {
-        op: I64MathOps::Sub,
-        left_arg: 0,
+    I64MathOp {
+        right_arg: 1,
+        right_reg_idx: 1,
+        ..I64MathOp::base(I64MathOps::Sub)
+    }
+}
+
+pub fn mul_op<Val: Field>() -> I64MathOp<Val> {
+    I64MathOp {
+        right_arg: 1,
+        right_reg_idx: 1,
+        ..I64MathOp::base(I64MathOps::Mul)
+    }
+}
+
+pub fn mulh_op<Val: Field>() -> I64MathOp<Val> {
+    I64MathOp {
+        right_arg: 1,
+        right_reg_idx: 1,
+        ..I64MathOp::base(I64MathOps::MulH)
+    }
+}
+
+pub fn smulh_op<Val: Field>() -> I64MathOp<Val> {
+    I64MathOp {
+        right_arg: 1,
+        right_reg_idx: 1,
+        ..I64MathOp::base(I64MathOps::SMulH)
+    }
+}
+
+pub fn xor_op<Val: Field>() -> I64MathOp<Val> {
+    I64MathOp {
+        right_arg: 1,
+        right_reg_idx: 1,
+        ..I64MathOp::base(I64MathOps::Xor)
+    }
+}
+
+pub fn ror_op<Val: Field>() -> I64MathOp<Val> {
+    I64MathOp {
+        right_arg: 1,
+        right_reg_idx: 1,
+        ..I64MathOp::base(I64MathOps::Ror)
+    }
+}
+
+pub fn rol_op<Val: Field>() -> I64MathOp<Val> {
+    I64MathOp {
+        right_arg: 1,
+        right_reg_idx: 1,
+        ..I64MathOp::base(I64MathOps::Rol)
+    }
+}
+
+pub fn neg_op<Val: Field>() -> I64MathOp<Val> {
+    I64MathOp::base(I64MathOps::Neg)
+}
+
+// res_reg_idx must be equal to left_reg_idx
+pub fn swap_op<Val: Field>() -> I64MathOp<Val> {
+    I64MathOp {
+        right_arg: 1,
+        right_reg_idx: 1,
+        ..I64MathOp::base(I64MathOps::Swap)
+    }
+}
+
+// f0 += a1
+pub fn fadd_op<Val: Field>() -> I64MathOp<Val> {
+    I64MathOp {
+        right_reg_idx: 1,
+        ..I64MathOp::base(I64MathOps::FAdd)
+    }
+}
+
+// f0 -= a1
+pub fn fsub_op<Val: Field>() -> I64MathOp<Val> {
+    I64MathOp {
+        right_reg_idx: 1,
+        ..I64MathOp::base(I64MathOps::FSub)
+    }
+}
+
+// e0 *= a1
+pub fn fmul_op<Val: Field>() -> I64MathOp<Val> {
+    I64MathOp {
+        right_reg_idx: 1,
+        ..I64MathOp::base(I64MathOps::FMul)
+    }
+}
+
+// e0 /= a1
+pub fn fdiv_op<Val: Field>() -> I64MathOp<Val> {
+    I64MathOp {
+        right_reg_idx: 1,
+        ..I64MathOp::base(I64MathOps::FDiv)
+    }
+}
+
+// e0 = sqrt(e0)
+pub fn fsqrt_op<Val: Field>() -> I64MathOp<Val> {
+    I64MathOp::base(I64MathOps::FSqrt)
+}
+
+// rounding mode = (r0 >>> imm32) & 3
+pub fn cfround_op<Val: Field>(imm32: u32) -> I64MathOp<Val> {
+    I64MathOp {
+        imm32,
+        ..I64MathOp::base(I64MathOps::CFRound)
+    }
+}
+
+// f0 ^= FSCAL_MASK
+pub fn fscal_op<Val: Field>() -> I64MathOp<Val> {
+    I64MathOp::base(I64MathOps::FScal)
+}
+
+// idx is an F register or an E register offset by FP_REGS_NUM
+pub fn fswap_op<Val: Field>(idx: u8) -> I64MathOp<Val> {
+    I64MathOp {
+        left_reg_idx: idx,
+        res_reg_idx: idx,
+        ..I64MathOp::base(I64MathOps::FSwap)
+    }
+}
+
+// cond must be in 0..=15 and target must be a pc of the program
+pub fn cbranch_op<Val: Field>(cond: u8, imm32: u32, target: u32) -> I64MathOp<Val> {
+    assert!(cond < 16, "CBranch conditions are 0 to 15");
+    I64MathOp {
+        imm32,
+        cond,
+        target,
+        ..I64MathOp::base(I64MathOps::CBranch)
+    }
+}
+
+// shift must be in 0..=3
+pub fn add_rs_op<Val: Field>(shift: u8, imm32: u32) -> I64MathOp<Val> {
+    assert!(shift < 4, "AddRs shifts by 0 to 3");
+    I64MathOp {
         right_arg: 1,
-        left_reg_idx: 0,
         right_reg_idx: 1,
-        res_reg_idx: 0,
-        _u: PhantomData,
+        imm32,
+        shift,
+        ..I64MathOp::base(I64MathOps::AddRs)
+    }
+}
+
+// imm32 must be neither zero nor a power of 2
+pub fn mul_rcp_op<Val: Field>(imm32: u32) -> I64MathOp<Val> {
+    assert!(
+        imm32 != 0 && !imm32.is_power_of_two(),
+        "MulRcp divisor {} is zero or a power of 2",
+        imm32
+    );
+    I64MathOp {
+        imm32,
+        ..I64MathOp::base(I64MathOps::MulRcp)
+    }
+}
+
+// [r0 + imm32] = r1 within the scratchpad level
+pub fn istore_op<Val: Field>(mem_level: ScratchpadLevel, imm32: u32) -> I64MathOp<Val> {
+    I64MathOp {
+        right_reg_idx: 1,
+        imm32,
+        mem_level,
+        ..I64MathOp::base(I64MathOps::IStore)
     }
 }
 
 pub fn no_op<Val: Field>() -> I64MathOp<Val> {
-    I64MathOp::<Val> {
-        op: I64MathOps::NoOp,
-        left_arg: 0,
-        right_arg: 0,
-        left_reg_idx: 0,
-        right_reg_idx: 0,
-        res_reg_idx: 0,
-        _u: PhantomData,
+    I64MathOp::base(I64MathOps::NoOp)
+}
+
+#[cfg(test)]
+mod tests {
+    use p3_baby_bear::BabyBear;
+    use p3_field::AbstractField;
+
+    use super::*;
+    use crate::debug_builder::{count_failures, row, row_mut, run_program, test_regs};
+
+    fn int_ops() -> Vec<I64MathOp<BabyBear>> {
+        let mut add_rs_same = add_rs_op(2, 0x8000_0001);
+        add_rs_same.left_reg_idx = 1;
+        add_rs_same.res_reg_idx = 1;
+        vec![
+            add_op(),
+            sub_op(),
+            mul_op(),
+            mulh_op(),
+            smulh_op(),
+            mul_rcp_op(3_000_000_019),
+            xor_op(),
+            ror_op(),
+            rol_op(),
+            neg_op(),
+            swap_op(),
+            add_rs_op(3, 0xc0de_0042),
+            add_rs_same,
+            add_op().with_imm(0x8000_0001),
+            sub_op().with_imm(0xffff_fff0),
+            mul_op().with_imm(0x8000_0003),
+            xor_op().with_imm(0x7fff_1234),
+            ror_op().with_imm(0xffff_ff07),
+            rol_op().with_imm(13),
+            cbranch_op(3, 0x5a5a_0101, 0),
+        ]
+    }
+
+    #[test]
+    fn int_ops_satisfy_constraints() {
+        for (r0, r1) in [
+            (65535, 4294901761),
+            (-1, i64::MIN),
+            (0x0123_4567_89ab_cdef, -0x0fed_cba9_8765_4321),
+        ] {
+            let (prog, trace, public_values) = run_program(int_ops(), test_regs(r0, r1), 2);
+            assert_eq!(
+                count_failures(&prog, &trace, &public_values),
+                0,
+                "r0 {} r1 {}",
+                r0,
+                r1
+            );
+        }
+    }
+
+    #[test]
+    fn sub_rejects_tampered_rows() {
+        let ops = vec![sub_op(), sub_op().with_imm(0xffff_fff0)];
+        let (prog, trace, public_values) = run_program(ops, test_regs(1000, 77), 1);
+        assert_eq!(count_failures(&prog, &trace, &public_values), 0);
+        for r in 1..=2 {
+            // a result that is not left - right
+            let mut tampered = trace.clone();
+            row_mut(&mut tampered, r).result[3] += BabyBear::one();
+            assert!(count_failures(&prog, &tampered, &public_values) > 0);
+
+            // res + right = left still holds but the right operand is not the source
+            let mut tampered = trace.clone();
+            let cols = row_mut(&mut tampered, r);
+            cols.right_arg[0] += BabyBear::one();
+            cols.result[0] -= BabyBear::one();
+            assert!(count_failures(&prog, &tampered, &public_values) > 0);
+        }
+    }
+
+    #[test]
+    fn flags_reject_tampered_rows() {
+        let (prog, trace, public_values) =
+            run_program(vec![add_op(), xor_op()], test_regs(1000, 77), 1);
+        assert_eq!(count_failures(&prog, &trace, &public_values), 0);
+        let last = trace.height() - 1;
+
+        // an Add row with the Sub flag set as well
+        let mut tampered = trace.clone();
+        row_mut(&mut tampered, 1).flags[I64MathOps::Sub.flag_idx()] = BabyBear::one();
+        assert!(count_failures(&prog, &tampered, &public_values) > 0);
+
+        // an Add row with 2 as its flag
+        let mut tampered = trace.clone();
+        row_mut(&mut tampered, 1).flags[I64MathOps::Add.flag_idx()] = BabyBear::two();
+        assert!(count_failures(&prog, &tampered, &public_values) > 0);
+
+        // a padding row with an unused flag set as well
+        let mut tampered = trace.clone();
+        row_mut(&mut tampered, last).flags[NUM_OPS] = BabyBear::one();
+        assert!(count_failures(&prog, &tampered, &public_values) > 0);
+
+        // a padding row without any flag
+        let mut tampered = trace.clone();
+        row_mut(&mut tampered, last).flags[I64MathOps::NoOp.flag_idx()] = BabyBear::zero();
+        assert!(count_failures(&prog, &tampered, &public_values) > 0);
+    }
+
+    #[test]
+    fn cbranch_rejects_tampered_rows() {
+        // the low byte of r2 = 0xff00 is zero so the first branch to pc 1 is taken, the
+        // branch adds to r2 and the second one is not
+        let mut regs = test_regs(1000, 77);
+        regs.int_regs[2] = 0xff00;
+        let mut branch = cbranch_op(0, 0, 1);
+        branch.left_reg_idx = 2;
+        branch.res_reg_idx = 2;
+        let ops = vec![xor_op(), add_op(), branch, mul_op()];
+        let (prog, trace, public_values) = run_program(ops, regs, 2);
+        assert_eq!(count_failures(&prog, &trace, &public_values), 0);
+        let pcs: Vec<_> = (1..=6).map(|r| row(&trace, r).pc).collect();
+        assert_eq!(pcs, [0, 1, 2, 1, 2, 3].map(BabyBear::from_canonical_u32));
+
+        for r in 1..=6 {
+            // a branch taken or not taken the other way
+            let mut tampered = trace.clone();
+            let cols = row_mut(&mut tampered, r);
+            cols.taken = BabyBear::one() - cols.taken;
+            assert!(count_failures(&prog, &tampered, &public_values) > 0);
+
+            // a jump to another pc
+            let mut tampered = trace.clone();
+            row_mut(&mut tampered, r).next_pc += BabyBear::one();
+            assert!(count_failures(&prog, &tampered, &public_values) > 0);
+        }
+    }
+
+    #[test]
+    fn mul_rcp_edge_divisors() {
+        for imm32 in [3, 0x8000_0001, u32::MAX] {
+            let (prog, trace, public_values) =
+                run_program(vec![mul_rcp_op(imm32)], test_regs(-1, i64::MAX), 3);
+            assert_eq!(
+                count_failures(&prog, &trace, &public_values),
+                0,
+                "imm32 {}",
+                imm32
+            );
+        }
+
+        for imm32 in [0, 1, 4, 1 << 31] {
+            let op = std::panic::catch_unwind(|| mul_rcp_op::<BabyBear>(imm32));
+            assert!(op.is_err(), "imm32 {}", imm32);
+        }
     }
 }
@@ -1,24 +1,38 @@
+use std::borrow::{Borrow, BorrowMut};
+
 use p3_air::{Air, AirBuilder, AirBuilderWithPublicValues, BaseAir};
 use p3_field::AbstractField;
-use p3_field::{Field, PrimeField};
+use p3_field::{Field, PrimeField32};
 use p3_matrix::{dense::RowMajorMatrix, Matrix};
 use sha2::{Digest, Sha256};
-use sp1_core_executor::{ExecutionRecord, Program};
-use sp1_stark::air::MachineAir;
+use sp1_primitives::consts::WORD_SIZE;
+use sp1_recursion_core::air::{
+    RecursionPublicValues, PV_DIGEST_NUM_WORDS, RECURSIVE_PROOF_NUM_PV_ELTS,
+};
+use sp1_stark::air::{MachineAir, MachineProgram, SP1AirBuilder};
 
-use crate::math_ops::{no_op, MathOpFirstRow};
-use crate::stark_primitives::LEFT_ARG;
+use crate::machine::ProgExecRecord;
+use crate::math_ops::{cfround_mode, no_op, I64MathOps, MathOpFirstRow};
+use crate::program_table::eval_program_lookup;
+use crate::range_check::{eval_range_check, populate_range_check};
+use crate::register::{eval_reg_file, populate_reg_file};
+use crate::scratchpad::{eval_memory, populate_memory_log};
+use crate::stark_primitives::{ProgExecCols, REG_FILE_SIZE};
 use crate::{math_ops::I64MathOp, register::RegFile, stark_primitives::BIN_OP_ROW_SIZE, Cli};
 
 pub fn dummy_32b_public_values(seed: u8) -> [u8; 32] {
     let mut public_values = [seed; 32];
     for i in 16..32 {
-        public_values[i] = seed+1;
+        public_values[i] = seed + 1;
     }
     public_values
 }
 
-pub fn dummy_public_values_hash(global_nonce: &[u8; 32], local_nonce: &[u8; 32], hash_value: &[u8; 32]) -> [u8; 32] {
+pub fn dummy_public_values_hash(
+    global_nonce: &[u8; 32],
+    local_nonce: &[u8; 32],
+    hash_value: &[u8; 32],
+) -> [u8; 32] {
     let mut hasher = Sha256::new();
     hasher.update(global_nonce);
     hasher.update(local_nonce);
@@ -30,102 +44,317 @@ pub fn to_field_values<F: p3_field::Field>(values: &[u8]) -> Vec<F> {
     values.iter().map(|&b| F::from_canonical_u8(b)).collect()
 }
 
+// Public values: the recursion public values, their committed value digest is the public
+// values hash, followed by the AIR segment: the number of real rows and the state after
+// and before the program. A state is the register file and the rounding mode.
+pub const PV_DIGEST_LEN: usize = PV_DIGEST_NUM_WORDS * WORD_SIZE;
+pub const PV_REAL_ROWS: usize = RECURSIVE_PROOF_NUM_PV_ELTS;
+pub const STATE_SIZE: usize = REG_FILE_SIZE + 1;
+pub const PV_FINAL_STATE: usize = PV_REAL_ROWS + 1;
+pub const PV_INIT_STATE: usize = PV_FINAL_STATE + STATE_SIZE;
+pub const NUM_PUBLIC_VALUES: usize = PV_INIT_STATE + STATE_SIZE;
+
+// final_regs is the register file after the program
+pub fn generate_public_values<F: Field>(
+    prog: &ProgExec<F>,
+    final_regs: &RegFile,
+    trace: &RowMajorMatrix<F>,
+) -> Vec<F> {
+    let hash: Vec<F> = to_field_values(&dummy_public_values_hash(
+        &prog.global_nonce,
+        &prog.local_nonce,
+        &prog.hash_value,
+    ));
+    let mut public_values = vec![F::zero(); RECURSIVE_PROOF_NUM_PV_ELTS];
+    let recursion_public_values: &mut RecursionPublicValues<F> =
+        public_values.as_mut_slice().borrow_mut();
+    for (digest_word, word) in recursion_public_values
+        .committed_value_digest
+        .iter_mut()
+        .zip(hash.chunks(WORD_SIZE))
+    {
+        *digest_word = word.iter().copied().collect();
+    }
+
+    let real_rows = trace
+        .values
+        .chunks(BIN_OP_ROW_SIZE)
+        .map(|row| -> &ProgExecCols<F> { row.borrow() })
+        .filter(|cols| cols.is_real == F::one())
+        .count();
+    public_values.push(F::from_canonical_usize(real_rows));
+    public_values.extend(final_regs.state::<F>());
+    public_values.extend(prog.init_state());
+    debug_assert_eq!(public_values.len(), NUM_PUBLIC_VALUES);
+    public_values
+}
+
 #[derive(Clone, Debug)]
 pub struct ProgExec<F: Field> {
     pub ops: Vec<I64MathOp<F>>,
     pub regs: RegFile,
-    pub global_nonce: [u8;32],
-    pub local_nonce: [u8;32],
-    pub hash_value: [u8;32],
+    pub global_nonce: [u8; 32],
+    pub local_nonce: [u8; 32],
+    pub hash_value: [u8; 32],
+}
+
+impl<F: Field> ProgExec<F> {
+    // the branch targets must be pcs of the program, the AIR binds them through the
+    // program lookup
+    pub fn new(
+        ops: Vec<I64MathOp<F>>,
+        regs: RegFile,
+        global_nonce: [u8; 32],
+        local_nonce: [u8; 32],
+        hash_value: [u8; 32],
+    ) -> Self {
+        for (pc, op) in ops.iter().enumerate() {
+            if matches!(op.op, I64MathOps::CBranch) {
+                assert!(
+                    (op.target as usize) < ops.len(),
+                    "CBranch at pc {} targets {} past the program",
+                    pc,
+                    op.target
+                );
+            }
+        }
+        Self {
+            ops,
+            regs,
+            global_nonce,
+            local_nonce,
+            hash_value,
+        }
+    }
+
+    // The program input: the state the program starts from. The verifier computes it from
+    // the register file before the execution.
+    pub fn init_state(&self) -> Vec<F> {
+        self.regs.state()
+    }
+}
+
+// the execution starts at the first op of the program
+impl<F: Field> MachineProgram<F> for ProgExec<F> {
+    fn pc_start(&self) -> F {
+        F::zero()
+    }
 }
 
+// The execution chip, a row per executed op. It does not depend on the program, the
+// rows look up their instructions in the program chip.
+#[derive(Default)]
+pub struct ProgExecChip;
+
 // This is a row size of a state representation.
 // Includes register file ATM.
-impl<F: Field> BaseAir<F> for ProgExec<F> {
+impl<F: Field> BaseAir<F> for ProgExecChip {
     fn width(&self) -> usize {
         BIN_OP_ROW_SIZE
     }
 }
 
-impl<AB: AirBuilder + AirBuilderWithPublicValues> Air<AB> for ProgExec<AB::F> {
+impl<AB: SP1AirBuilder + AirBuilderWithPublicValues> Air<AB> for ProgExecChip {
     fn eval(&self, builder: &mut AB) {
         let main = builder.main();
         let local = main.row_slice(0);
         let next = main.row_slice(1);
+        let first_row: &MathOpFirstRow<AB::Var> = (*local).borrow();
+        let local: &ProgExecCols<AB::Var> = (*local).borrow();
+        let next: &ProgExecCols<AB::Var> = (*next).borrow();
 
-        // Use borrow trait for a FirstRow?
+        // the committed value digest bytes are the public values hash of the first row
         let pub_values = builder.public_values().to_vec();
-        // let local_pub_values = pub_values.clone();
         let mut when_first_row = builder.when_first_row();
-        // WIP hardcode
-        println!("public_values len {}", pub_values.len());
-        for i in 0..pub_values.len().min(32usize) {
-            when_first_row.assert_eq(pub_values[i], local[i + LEFT_ARG-1]);
+        for i in 0..PV_DIGEST_LEN {
+            when_first_row.assert_eq(pub_values[i], first_row.public_values_sha256[i]);
         }
 
+        builder.when_first_row().assert_zero(local.cnt);
+        builder
+            .when_transition()
+            .assert_eq(next.cnt, local.cnt + AB::Expr::one());
+
+        // The real rows follow the first row and the padding NoOps follow them. The row
+        // counter of the last real row is the number of real rows, or there are none.
+        let real_rows: AB::Expr = pub_values[PV_REAL_ROWS].into();
+        let not_first_row = AB::Expr::one() - builder.is_first_row();
+        let real_step = local.is_real - next.is_real;
+        builder.assert_eq(
+            local.is_real,
+            not_first_row.clone() - local.flags[I64MathOps::NoOp.flag_idx()],
+        );
+        builder.when_last_row().assert_zero(local.is_real);
+        builder
+            .when_transition()
+            .when(not_first_row.clone())
+            .assert_bool(real_step.clone());
         builder
             .when_transition()
-            .assert_eq(next[0], local[0] + AB::Expr::one());
+            .when(not_first_row.clone())
+            .assert_zero(real_step * (local.cnt - real_rows.clone()));
+        builder
+            .when_first_row()
+            .assert_zero((AB::Expr::one() - next.is_real) * real_rows);
+
+        // the first row holds the claimed initial state, the last row is padding,
+        // it holds the state after the last real row
+        let mut fprc_sel_sum = AB::Expr::zero();
+        let mut fprc = AB::Expr::zero();
+        let mut next_fprc = AB::Expr::zero();
+        for k in 0..4 {
+            builder.assert_bool(local.fprc_sel[k]);
+            fprc_sel_sum = fprc_sel_sum + local.fprc_sel[k];
+            fprc = fprc + local.fprc_sel[k] * AB::F::from_canonical_usize(k);
+            next_fprc = next_fprc + next.fprc_sel[k] * AB::F::from_canonical_usize(k);
+        }
+        builder.assert_one(fprc_sel_sum);
+        let state = local
+            .reg_int
+            .iter()
+            .chain(local.reg_fp.iter())
+            .map(|&reg| reg.into())
+            .chain([fprc.clone()])
+            .collect::<Vec<AB::Expr>>();
+        let mut when_first_row = builder.when_first_row();
+        for (i, value) in state.iter().enumerate() {
+            when_first_row.assert_eq(value.clone(), pub_values[PV_INIT_STATE + i]);
+        }
+        let mut when_last_row = builder.when_last_row();
+        for (i, value) in state.into_iter().enumerate() {
+            when_last_row.assert_eq(value, pub_values[PV_FINAL_STATE + i]);
+        }
+
+        // The program counter chain starts at the second row. The sequential next pc
+        // wraps to the program start after the last op, a taken branch jumps to the target.
+        // The real rows look up pc_end and the target with their instruction.
+        builder.when_first_row().assert_zero(local.next_pc);
+        builder.when_transition().assert_eq(next.pc, local.next_pc);
+
+        let mut when_not_first_row = builder.when(not_first_row);
+        when_not_first_row.assert_eq(
+            local.seq_pc,
+            (local.pc + AB::F::one()) * (AB::Expr::one() - local.pc_end),
+        );
+        when_not_first_row.assert_eq(
+            local.next_pc,
+            local.seq_pc + local.taken * (local.prog_target - local.seq_pc),
+        );
+
+        // only CFRound changes the rounding mode
+        let is_cfround = local.flags[I64MathOps::CFRound.flag_idx()];
+        builder.when_transition().assert_eq(
+            next_fprc,
+            fprc.clone() + is_cfround * (cfround_mode::<AB>(local) - fprc),
+        );
+
+        eval_reg_file(builder);
+        eval_memory(builder);
+        eval_range_check(builder);
+        eval_program_lookup(builder);
 
         let op = I64MathOp::default();
         op.eval(builder);
     }
 }
 
-pub fn generate_program_trace<F: Field>(prog: &mut ProgExec<F>, cli: &Cli) -> RowMajorMatrix<F> {
+// Runs the program from its initial state, returns the trace and the register file after
+// the program.
+pub fn generate_program_trace<F: PrimeField32>(
+    prog: &ProgExec<F>,
+    cli: &Cli,
+) -> (RowMajorMatrix<F>, RegFile) {
+    let mut regs = prog.regs.clone();
+    let mut ops = prog.ops.clone();
     let num_of_ops = prog.ops.len() * cli.repetitions as usize * cli.programs as usize + 1;
     let next_pow_of_2 = num_of_ops.next_power_of_two();
     let mut values = Vec::with_capacity(BIN_OP_ROW_SIZE * next_pow_of_2);
 
-    let public_values: Vec<F> = to_field_values(&dummy_public_values_hash(&prog.global_nonce, &prog.local_nonce, &prog.hash_value));
+    let public_values: Vec<F> = to_field_values(&dummy_public_values_hash(
+        &prog.global_nonce,
+        &prog.local_nonce,
+        &prog.hash_value,
+    ));
     let public_values_array = public_values.try_into().expect("must be 32 bytes");
-    let mut first_row = MathOpFirstRow::new(public_values_array).consume_as_vec();
+    let mut first_row = MathOpFirstRow::populate(public_values_array);
+    let first_row_cols: &mut ProgExecCols<F> = first_row.as_mut_slice().borrow_mut();
+    populate_reg_file(first_row_cols, &regs.packed());
+    first_row_cols.fprc_sel[regs.fprc as usize] = F::one();
     // let first_row = vec![F::zero(); BIN_OP_ROW_SIZE];
     println!("generate_program_trace first_row len {}", first_row.len());
 
-    values.append(&mut first_row); 
+    values.append(&mut first_row);
 
     // println!("generate_program_trace first_row {:?}", first_row);
     // values.append(&mut first_row);
-    prog.regs.cnt += 1;
+    regs.cnt += 1;
 
     // println!("generate_program_trace first_row {:?}", values);
 
+    let prog_len = prog.ops.len() as u32;
     for _ in 0..cli.programs {
         for _ in 0..cli.repetitions {
-            for op in prog.ops.iter_mut() {
-                let mut next_record = op.generate(&mut prog.regs, &mut values);
+            // taken branches jump back so a repetition might take more rows than ops
+            while regs.pc < prog_len {
+                let pc = regs.pc;
+                let mut next_record = ops[pc as usize].generate(&mut regs, &mut values);
+                populate_pc_trace_record(&mut next_record, pc, regs.pc, prog_len);
                 values.append(&mut next_record);
             }
+            regs.pc = 0;
         }
     }
 
     // find the next power of 2 and fill up the Matrix with NoOps up to the next pow of 2
-    fill_up_with_no_ops(&mut values, &mut prog.regs);
+    fill_up_with_no_ops(&mut values, &mut regs, prog_len);
+    populate_memory_log(&mut values, &regs.scratchpad.log);
+    populate_range_check(&mut values);
 
     println!(
         "generate_program_trace values.len() {:?}  rows {}",
         values.len(),
         values.len() / BIN_OP_ROW_SIZE
     );
-    RowMajorMatrix::new(values, BIN_OP_ROW_SIZE)
+    (RowMajorMatrix::new(values, BIN_OP_ROW_SIZE), regs)
+}
+
+// the sequential next pc after the last op of the program is 0
+fn populate_pc_trace_record<F: Field>(
+    trace_record: &mut [F],
+    pc: u32,
+    next_pc: u32,
+    prog_len: u32,
+) {
+    let pc_end = pc + 1 == prog_len;
+    let seq_pc = if pc_end { 0 } else { pc + 1 };
+
+    let cols: &mut ProgExecCols<F> = trace_record.borrow_mut();
+    cols.pc = F::from_canonical_u32(pc);
+    cols.next_pc = F::from_canonical_u32(next_pc % prog_len);
+    cols.seq_pc = F::from_canonical_u32(seq_pc);
+    cols.pc_end = F::from_bool(pc_end);
 }
 
-fn fill_up_with_no_ops<F: Field>(values: &mut Vec<F>, reg_file: &mut RegFile) {
+// NoOps keep on sequentially running over the program pc.
+// There is at least one NoOp as the last row holds the state after the last real row.
+fn fill_up_with_no_ops<F: Field>(values: &mut Vec<F>, reg_file: &mut RegFile, prog_len: u32) {
     let actual_num_of_ops = values.len() / BIN_OP_ROW_SIZE;
-    let next_pow_of_2 = actual_num_of_ops.next_power_of_two();
+    let next_pow_of_2 = (actual_num_of_ops + 1).next_power_of_two();
     let mut no_op = no_op();
 
     for _ in actual_num_of_ops..next_pow_of_2 {
+        let pc = reg_file.pc;
         let mut next_record = no_op.generate(reg_file, values);
+        populate_pc_trace_record(&mut next_record, pc, reg_file.pc, prog_len);
+        reg_file.pc %= prog_len;
         values.append(&mut next_record);
     }
 }
 
-impl<F: PrimeField> MachineAir<F> for ProgExec<F> {
-    type Record = ExecutionRecord;
+impl<F: PrimeField32> MachineAir<F> for ProgExecChip {
+    type Record = ProgExecRecord<F>;
 
-    type Program = Program;
+    type Program = ProgExec<F>;
 
     fn name(&self) -> String {
         "ProgExec".to_string()
@@ -133,17 +362,94 @@ impl<F: PrimeField> MachineAir<F> for ProgExec<F> {
 
     fn generate_trace(
         &self,
-        _input: &Self::Record,
+        input: &Self::Record,
         _output: &mut Self::Record,
     ) -> RowMajorMatrix<F> {
-        todo!()
+        RowMajorMatrix::new(input.exec.clone(), BIN_OP_ROW_SIZE)
     }
 
     fn included(&self, _shard: &Self::Record) -> bool {
-        todo!()
+        true
     }
+}
 
-    fn preprocessed_width(&self) -> usize {
-        BIN_OP_ROW_SIZE
+#[cfg(test)]
+mod tests {
+    use p3_baby_bear::BabyBear;
+
+    use super::*;
+    use crate::debug_builder::{count_failures, row_mut, run_program, test_regs};
+    use crate::math_ops::{add_op, mul_op, xor_op};
+    use crate::stark_primitives::{INT_REGS, NUM_FLAGS};
+
+    #[test]
+    fn padding_rejects_hidden_real_rows() {
+        // 9 real rows after the first row, the rows 10 to 15 are padding
+        let ops = vec![add_op(), mul_op(), xor_op()];
+        let (prog, trace, public_values) = run_program(ops, test_regs(1000, 77), 3);
+        assert_eq!(trace.height(), 16);
+        assert_eq!(public_values[PV_REAL_ROWS], BabyBear::from_canonical_u32(9));
+        assert_eq!(count_failures(&prog, &trace, &public_values), 0);
+
+        // a real row after the padding rows started
+        let mut tampered = trace.clone();
+        let cols = row_mut(&mut tampered, 12);
+        cols.flags[I64MathOps::NoOp.flag_idx()] = BabyBear::zero();
+        cols.flags[I64MathOps::Add.flag_idx()] = BabyBear::one();
+        cols.is_real = BabyBear::one();
+        assert!(count_failures(&prog, &tampered, &public_values) > 0);
+
+        // a padding row with a result
+        let mut tampered = trace.clone();
+        row_mut(&mut tampered, 12).result[0] = BabyBear::one();
+        assert!(count_failures(&prog, &tampered, &public_values) > 0);
+
+        // a padding row among the real rows
+        let mut tampered = trace.clone();
+        let cols = row_mut(&mut tampered, 5);
+        cols.flags = [BabyBear::zero(); NUM_FLAGS];
+        cols.flags[I64MathOps::NoOp.flag_idx()] = BabyBear::one();
+        cols.is_real = BabyBear::zero();
+        assert!(count_failures(&prog, &tampered, &public_values) > 0);
+
+        // a wrong number of real rows
+        for real_rows in [0, 8, 10] {
+            let mut tampered_values = public_values.clone();
+            tampered_values[PV_REAL_ROWS] = BabyBear::from_canonical_u32(real_rows);
+            assert!(count_failures(&prog, &trace, &tampered_values) > 0);
+        }
+    }
+
+    #[test]
+    fn final_state_is_bound() {
+        let ops = vec![add_op(), mul_op(), xor_op()];
+        let (prog, trace, public_values) = run_program(ops, test_regs(1000, 77), 2);
+        assert_eq!(count_failures(&prog, &trace, &public_values), 0);
+
+        // an integer register limb, an FP register limb and the rounding mode
+        for i in [0, 3 * INT_REGS + 7, STATE_SIZE - 1] {
+            let mut tampered_values = public_values.clone();
+            tampered_values[PV_FINAL_STATE + i] += BabyBear::one();
+            assert!(count_failures(&prog, &trace, &tampered_values) > 0);
+        }
+    }
+
+    #[test]
+    fn init_state_is_bound() {
+        let ops = vec![add_op(), mul_op(), xor_op()];
+        let (prog, trace, public_values) = run_program(ops, test_regs(1000, 77), 2);
+        assert_eq!(count_failures(&prog, &trace, &public_values), 0);
+
+        // the public initial state is not the first row register file
+        for i in [1, 3 * INT_REGS + 7, STATE_SIZE - 1] {
+            let mut tampered_values = public_values.clone();
+            tampered_values[PV_INIT_STATE + i] += BabyBear::one();
+            assert!(count_failures(&prog, &trace, &tampered_values) > 0);
+        }
+
+        // the first row starts from another register file than the public one
+        let mut tampered = trace.clone();
+        row_mut(&mut tampered, 0).reg_int[1] += BabyBear::one();
+        assert!(count_failures(&prog, &tampered, &public_values) > 0);
     }
 }
@@ -0,0 +1,249 @@
+use std::borrow::Borrow;
+
+use p3_air::{Air, AirBuilder, BaseAir, PairBuilder};
+use p3_field::AbstractField;
+use p3_field::{Field, PrimeField32};
+use p3_matrix::dense::RowMajorMatrix;
+use p3_matrix::Matrix;
+use sp1_stark::air::{AirInteraction, InteractionScope, MachineAir, SP1AirBuilder};
+use sp1_stark::InteractionKind;
+
+use crate::fp_ops::e_mask_word;
+use crate::machine::ProgExecRecord;
+use crate::math_ops::{flag, I64MathOp, I64MathOps, ALL_OPS};
+use crate::prog_exec::ProgExec;
+use crate::scratchpad::ScratchpadLevel;
+use crate::stark_primitives::{ProgExecCols, BIN_OP_ROW_SIZE, PROG_WORDS};
+
+// Program table columns: pc, the instruction words and the flag that pc is the last
+// instruction of the program
+pub const PROG_PC: usize = 0;
+pub const PROG_INSTR: usize = 1;
+pub const PROG_END: usize = PROG_INSTR + PROG_WORDS;
+pub const PROG_TABLE_WIDTH: usize = PROG_END + 1;
+
+// ops taking imm32 whatever their register indices are
+const IMM32_OPS: [I64MathOps; 5] = [
+    I64MathOps::MulRcp,
+    I64MathOps::CFRound,
+    I64MathOps::AddRs,
+    I64MathOps::CBranch,
+    I64MathOps::IStore,
+];
+
+// An instruction is encoded as: op flag index + 1, left, right and result register
+// indices, imm32 low and high halves, memory operand, scratchpad level index + 1,
+// AddRs shift, CBranch condition and target, FDiv memory operand E masks. The words
+// the op does not use are 0, so are all the words of a table row past the program.
+pub fn instruction_words<F: Field>(op: &I64MathOp<F>) -> [u32; PROG_WORDS] {
+    let use_imm = op.op.has_imm_operand() && op.src_is_dst();
+    let imm32 = if use_imm
+        || op.mem_operand
+        || IMM32_OPS.iter().any(|o| o.flag_idx() == op.op.flag_idx())
+    {
+        op.op_imm32()
+    } else {
+        0
+    };
+    // the memory operand address is imm32 masked by L3 if src is dst
+    let level = if op.mem_operand && op.src_is_dst() {
+        Some(ScratchpadLevel::L3)
+    } else if op.mem_operand || matches!(op.op, I64MathOps::IStore) {
+        Some(op.mem_level)
+    } else {
+        None
+    };
+    let (shift, cond, target) = match op.op {
+        I64MathOps::AddRs => (op.shift as u32, 0, 0),
+        I64MathOps::CBranch => (0, op.cond as u32, op.target),
+        _ => (0, 0, 0),
+    };
+    let e_mask = match op.op {
+        I64MathOps::FDiv if op.mem_operand => op.e_mask.map(e_mask_word),
+        _ => [0, 0],
+    };
+    [
+        op.op.flag_idx() as u32 + 1,
+        op.left_reg_idx as u32,
+        op.right_reg_idx as u32,
+        op.res_reg_idx as u32,
+        imm32 & 0xffff,
+        imm32 >> 16,
+        op.mem_operand as u32,
+        level.map_or(0, |l| l.sel_idx() as u32 + 1),
+        shift,
+        cond,
+        target,
+        e_mask[0],
+        e_mask[1],
+    ]
+}
+
+// The program table, a row per instruction ordered by pc. The rows past the program
+// have no instruction and are never looked up.
+pub fn program_table<F: Field>(ops: &[I64MathOp<F>]) -> RowMajorMatrix<F> {
+    let rows = ops.len().next_power_of_two();
+    let mut values = vec![F::zero(); rows * PROG_TABLE_WIDTH];
+    for (pc, row) in values.chunks_mut(PROG_TABLE_WIDTH).enumerate() {
+        row[PROG_PC] = F::from_canonical_usize(pc);
+        if let Some(op) = ops.get(pc) {
+            for (v, w) in row[PROG_INSTR..PROG_END]
+                .iter_mut()
+                .zip(instruction_words(op))
+            {
+                *v = F::from_canonical_u32(w);
+            }
+        }
+        row[PROG_END] = F::from_bool(pc + 1 == ops.len());
+    }
+    RowMajorMatrix::new(values, PROG_TABLE_WIDTH)
+}
+
+// Every real row looks up its pc, instruction words and pc_end in the program table.
+// The op specific words are materialized in their own columns to keep the lookup linear.
+pub fn eval_program_lookup<AB: SP1AirBuilder>(builder: &mut AB) {
+    let main = builder.main();
+    let local = main.row_slice(0);
+    let local: &ProgExecCols<AB::Var> = (*local).borrow();
+
+    let mut shift = AB::Expr::zero();
+    for j in 0..4 {
+        shift = shift + local.add_rs().shift_sel[j] * AB::F::from_canonical_usize(j);
+    }
+    builder
+        .when(flag(local, I64MathOps::AddRs))
+        .assert_eq(local.prog_shift, shift);
+    let mut cond = AB::Expr::zero();
+    for k in 0..16 {
+        cond = cond + local.cbranch().cond_sel[k] * AB::F::from_canonical_usize(k);
+    }
+    builder
+        .when(flag(local, I64MathOps::CBranch))
+        .assert_eq(local.prog_cond, cond);
+
+    let mut op_word = AB::Expr::zero();
+    for op in ALL_OPS {
+        op_word = op_word + flag(local, op) * AB::F::from_canonical_usize(op.flag_idx() + 1);
+    }
+    let mut level = AB::Expr::zero();
+    for k in 0..3 {
+        level = level + local.mem_level_sel[k] * AB::F::from_canonical_usize(k + 1);
+    }
+    let base = AB::F::from_canonical_u32(256);
+    let values = vec![
+        local.pc.into(),
+        op_word,
+        local.left_reg_idx.into(),
+        local.right_reg_idx.into(),
+        local.res_reg_idx.into(),
+        local.imm[0] + local.imm[1] * base,
+        local.imm[2] + local.imm[3] * base,
+        local.mem_operand.into(),
+        level,
+        local.prog_shift.into(),
+        local.prog_cond.into(),
+        local.prog_target.into(),
+        local.prog_e_mask[0].into(),
+        local.prog_e_mask[1].into(),
+        local.pc_end.into(),
+    ];
+    builder.send(
+        AirInteraction::new(values, local.is_real.into(), InteractionKind::Program),
+        InteractionScope::Local,
+    );
+}
+
+// The program table is the preprocessed trace committed in the verifying key, the main
+// trace holds the number of lookups of every instruction.
+#[derive(Default)]
+pub struct ProgramChip;
+
+impl<F: Field> BaseAir<F> for ProgramChip {
+    fn width(&self) -> usize {
+        1
+    }
+}
+
+impl<F: PrimeField32> MachineAir<F> for ProgramChip {
+    type Record = ProgExecRecord<F>;
+
+    type Program = ProgExec<F>;
+
+    fn name(&self) -> String {
+        "Program".to_string()
+    }
+
+    fn generate_trace(
+        &self,
+        input: &Self::Record,
+        _output: &mut Self::Record,
+    ) -> RowMajorMatrix<F> {
+        let mut mult = vec![F::zero(); input.prog_len.next_power_of_two()];
+        for row in input.exec.chunks(BIN_OP_ROW_SIZE) {
+            let cols: &ProgExecCols<F> = row.borrow();
+            if cols.is_real == F::one() {
+                mult[cols.pc.as_canonical_u32() as usize] += F::one();
+            }
+        }
+        RowMajorMatrix::new(mult, 1)
+    }
+
+    fn included(&self, _shard: &Self::Record) -> bool {
+        true
+    }
+
+    fn preprocessed_width(&self) -> usize {
+        PROG_TABLE_WIDTH
+    }
+
+    fn generate_preprocessed_trace(&self, program: &Self::Program) -> Option<RowMajorMatrix<F>> {
+        Some(program_table(&program.ops))
+    }
+}
+
+impl<AB: SP1AirBuilder + PairBuilder> Air<AB> for ProgramChip {
+    fn eval(&self, builder: &mut AB) {
+        let main = builder.main();
+        let preprocessed = builder.preprocessed();
+        let mult = main.row_slice(0)[0];
+        let table: Vec<AB::Expr> = preprocessed
+            .row_slice(0)
+            .iter()
+            .map(|&v| v.into())
+            .collect();
+        builder.receive(
+            AirInteraction::new(table, mult.into(), InteractionKind::Program),
+            InteractionScope::Local,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::debug_builder::{count_failures, run_program, test_regs};
+    use crate::math_ops::{add_op, cbranch_op, mul_op, xor_op};
+
+    // the trace of a program run does not satisfy the program table of another program
+    #[test]
+    fn program_table_rejects_other_programs() {
+        let ops = vec![
+            add_op(),
+            xor_op().with_imm(0x7fff_1234),
+            cbranch_op(3, 0x5a5a_0101, 0),
+        ];
+        let (prog, trace, public_values) = run_program(ops, test_regs(1000, 77), 2);
+        assert_eq!(count_failures(&prog, &trace, &public_values), 0);
+
+        let mut other_imm = prog.clone();
+        other_imm.ops[1] = xor_op().with_imm(0x7fff_1235);
+        let mut other_op = prog.clone();
+        other_op.ops[0] = mul_op();
+        let mut other_target = prog.clone();
+        other_target.ops[2] = cbranch_op(3, 0x5a5a_0101, 1);
+        let mut longer = prog.clone();
+        longer.ops.push(add_op());
+        for other in [other_imm, other_op, other_target, longer] {
+            assert!(count_failures(&other, &trace, &public_values) > 0);
+        }
+    }
+}
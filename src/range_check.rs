@@ -0,0 +1,402 @@
+use std::borrow::{Borrow, BorrowMut};
+
+use p3_air::{Air, BaseAir, PairBuilder};
+use p3_field::AbstractField;
+use p3_field::{Field, PrimeField32};
+use p3_matrix::dense::RowMajorMatrix;
+use p3_matrix::Matrix;
+use sp1_stark::air::{AirInteraction, InteractionScope, MachineAir, SP1AirBuilder};
+use sp1_stark::InteractionKind;
+
+use crate::machine::ProgExecRecord;
+use crate::math_ops::{flag, I64MathOps};
+use crate::prog_exec::ProgExec;
+use crate::stark_primitives::{ProgExecCols, BIN_OP_ROW_SIZE, RANGE_SLOTS};
+
+// Byte table columns: a row per pair of bytes a, b and their XOR
+pub const BYTE_A: usize = 0;
+pub const BYTE_B: usize = 1;
+pub const BYTE_XOR: usize = 2;
+pub const BYTE_TABLE_WIDTH: usize = 3;
+pub const BYTE_TABLE_ROWS: usize = 1 << 16;
+
+// The first value of a byte lookup, the ByteChip has a multiplicity column per opcode
+#[derive(Clone, Copy)]
+pub enum ByteOpcode {
+    // both bytes are in 0..256, the third value is 0
+    U8Range = 0,
+    // the third value is the XOR of the bytes
+    Xor = 1,
+}
+
+pub const NUM_BYTE_OPS: usize = 2;
+
+// ops with op specific byte limbs
+pub const RANGE_CHECKED_OPS: [I64MathOps; 13] = [
+    I64MathOps::Mul,
+    I64MathOps::MulH,
+    I64MathOps::SMulH,
+    I64MathOps::MulRcp,
+    I64MathOps::Ror,
+    I64MathOps::Rol,
+    I64MathOps::CFRound,
+    I64MathOps::AddRs,
+    I64MathOps::FAdd,
+    I64MathOps::FSub,
+    I64MathOps::FMul,
+    I64MathOps::FDiv,
+    I64MathOps::FSqrt,
+];
+
+// A limb is a column and an offset added before the lookup.
+// A 7-bit limb is looked up twice, as is and with the offset 128, and so are a 6-bit limb
+// with the offset 192 and a 4-bit limb with the offset 240.
+fn push_bytes<T: Copy>(limbs: &mut Vec<(T, u32)>, cols: &[T]) {
+    limbs.extend(cols.iter().map(|&c| (c, 0)));
+}
+
+fn push_7bits<T: Copy>(limbs: &mut Vec<(T, u32)>, col: T) {
+    limbs.extend([(col, 0), (col, 128)]);
+}
+
+fn push_6bits<T: Copy>(limbs: &mut Vec<(T, u32)>, col: T) {
+    limbs.extend([(col, 0), (col, 192)]);
+}
+
+fn push_4bits<T: Copy>(limbs: &mut Vec<(T, u32)>, col: T) {
+    limbs.extend([(col, 0), (col, 240)]);
+}
+
+// operands, results, carries, imm32 and src register value limbs checked in every row
+pub fn shared_range_limbs<T: Copy>(local: &ProgExecCols<T>) -> Vec<(T, u32)> {
+    let mut limbs = Vec::new();
+    push_bytes(&mut limbs, &local.left_arg);
+    push_bytes(&mut limbs, &local.right_arg);
+    push_bytes(&mut limbs, &local.result);
+    push_bytes(&mut limbs, &local.carry);
+    push_bytes(&mut limbs, &local.imm);
+    push_bytes(&mut limbs, &local.src_val);
+    push_bytes(&mut limbs, &local.left_arg_hi);
+    push_bytes(&mut limbs, &local.right_arg_hi);
+    push_bytes(&mut limbs, &local.result_hi);
+    push_7bits(&mut limbs, local.imm_low7);
+    limbs
+}
+
+// limbs of the op aux columns, the carry limbs of the FP ops are split into lo and hi bytes
+pub fn op_range_limbs<T: Copy>(local: &ProgExecCols<T>, op: I64MathOps) -> Vec<(T, u32)> {
+    let mut limbs = Vec::new();
+    match op {
+        I64MathOps::Mul | I64MathOps::MulH | I64MathOps::SMulH | I64MathOps::MulRcp => {
+            let mul = local.mul();
+            push_bytes(&mut limbs, &mul.product);
+            push_bytes(&mut limbs, &mul.carry);
+            push_7bits(&mut limbs, mul.left_low7);
+            push_7bits(&mut limbs, mul.right_low7);
+            push_bytes(&mut limbs, &mul.smulh_carry);
+            if let I64MathOps::MulRcp = op {
+                push_bytes(&mut limbs, &mul.rcp_rem);
+                push_bytes(&mut limbs, &mul.rcp_rem_diff);
+                push_7bits(&mut limbs, mul.rcp_top);
+                push_bytes(&mut limbs, &mul.rcp_carry);
+            }
+        }
+        I64MathOps::Ror | I64MathOps::Rol | I64MathOps::CFRound => {
+            push_bytes(&mut limbs, &local.rotate().hi);
+            push_bytes(&mut limbs, &local.rotate().lo);
+        }
+        I64MathOps::AddRs => push_bytes(&mut limbs, &[local.add_rs().carry_top]),
+        I64MathOps::FAdd
+        | I64MathOps::FSub
+        | I64MathOps::FMul
+        | I64MathOps::FDiv
+        | I64MathOps::FSqrt => {
+            for lane in 0..2 {
+                let lane = local.fp_lane(lane);
+                let round = match op {
+                    I64MathOps::FAdd | I64MathOps::FSub => {
+                        let fadd = lane.fadd();
+                        push_bytes(&mut limbs, &fadd.cmp);
+                        push_bytes(&mut limbs, &fadd.cap_diff);
+                        push_bytes(&mut limbs, &fadd.align_lo);
+                        push_bytes(&mut limbs, &fadd.align_hi);
+                        push_bytes(&mut limbs, &fadd.sum);
+                        push_bytes(&mut limbs, &fadd.norm_lo);
+                        push_bytes(&mut limbs, &fadd.norm_hi);
+                        // the rounded significand is not the result on an overflow
+                        push_bytes(&mut limbs, &fadd.rnd[..6]);
+                        push_4bits(&mut limbs, fadd.rnd[6]);
+                        &fadd.round
+                    }
+                    I64MathOps::FMul => {
+                        let fmul = lane.fmul();
+                        push_bytes(&mut limbs, &fmul.product[..13]);
+                        push_bytes(&mut limbs, &fmul.carry);
+                        push_bytes(&mut limbs, &fmul.lo[..13]);
+                        push_bytes(&mut limbs, &fmul.hi);
+                        &fmul.round
+                    }
+                    I64MathOps::FDiv => {
+                        let fdiv = lane.fdiv();
+                        push_bytes(&mut limbs, &fdiv.quot);
+                        push_bytes(&mut limbs, &fdiv.rem);
+                        push_bytes(&mut limbs, &fdiv.rem_diff);
+                        push_bytes(&mut limbs, &fdiv.lo);
+                        push_bytes(&mut limbs, &fdiv.hi);
+                        push_bytes(&mut limbs, &fdiv.carry);
+                        // the low mantissa and mask limbs are looked up by their XOR
+                        let mem = &fdiv.mem;
+                        push_bytes(&mut limbs, &mem.int);
+                        push_7bits(&mut limbs, mem.int_low7);
+                        push_bytes(&mut limbs, &mem.abs);
+                        push_bytes(&mut limbs, &mem.lo);
+                        push_bytes(&mut limbs, &mem.hi);
+                        push_bytes(&mut limbs, &mem.mant[3..6]);
+                        push_4bits(&mut limbs, mem.mant[6]);
+                        push_6bits(&mut limbs, mem.mask[2]);
+                        push_4bits(&mut limbs, mem.mask_exp);
+                        &fdiv.round
+                    }
+                    _ => {
+                        let fsqrt = lane.fsqrt();
+                        push_bytes(&mut limbs, &fsqrt.root);
+                        push_bytes(&mut limbs, &fsqrt.rem);
+                        push_bytes(&mut limbs, &fsqrt.rem_diff);
+                        push_bytes(&mut limbs, &fsqrt.lo);
+                        push_bytes(&mut limbs, &fsqrt.hi);
+                        push_bytes(&mut limbs, &fsqrt.carry);
+                        &fsqrt.round
+                    }
+                };
+                // the rounding lsb is the parity of the low significand limb
+                push_7bits(&mut limbs, round.lsb_half);
+            }
+        }
+        _ => {}
+    }
+    limbs
+}
+
+// Every row looks up its limbs in pairs in the byte table of the ByteChip, the last
+// shared limb might be paired with 0. The op specific limbs are first copied to the slots,
+// so a row has the same lookups whatever its op is.
+pub fn eval_range_check<AB: SP1AirBuilder>(builder: &mut AB) {
+    let main = builder.main();
+    let local = main.row_slice(0);
+    let local: &ProgExecCols<AB::Var> = (*local).borrow();
+
+    let limb =
+        |(col, offset): (AB::Var, u32)| -> AB::Expr { col + AB::Expr::from_canonical_u32(offset) };
+
+    // a slot holds the limb of the op set in the row or 0
+    let mut slots = vec![AB::Expr::zero(); RANGE_SLOTS];
+    for op in RANGE_CHECKED_OPS {
+        let is_op = flag(local, op);
+        for (j, l) in op_range_limbs(local, op).into_iter().enumerate() {
+            slots[j] = slots[j].clone() + limb(l) * is_op;
+        }
+    }
+    for (j, slot) in slots.into_iter().enumerate() {
+        builder.assert_eq(local.range_slot[j], slot);
+    }
+
+    let shared: Vec<AB::Expr> = shared_range_limbs(local).into_iter().map(limb).collect();
+    let slots: Vec<AB::Expr> = local.range_slot.iter().map(|&c| c.into()).collect();
+    for pair in shared.chunks(2).chain(slots.chunks(2)) {
+        let values = vec![
+            AB::Expr::from_canonical_u8(ByteOpcode::U8Range as u8),
+            pair[0].clone(),
+            pair.get(1).cloned().unwrap_or(AB::Expr::zero()),
+            AB::Expr::zero(),
+        ];
+        builder.send(
+            AirInteraction::new(values, AB::Expr::one(), InteractionKind::Byte),
+            InteractionScope::Local,
+        );
+    }
+}
+
+// the byte pairs a row looks up, in the order of eval_range_check
+fn range_pairs<F: Field>(row: &ProgExecCols<F>) -> Vec<(F, F)> {
+    let limb = |(col, offset): (F, u32)| col + F::from_canonical_u32(offset);
+    let shared: Vec<F> = shared_range_limbs(row).into_iter().map(limb).collect();
+    shared
+        .chunks(2)
+        .chain(row.range_slot.chunks(2))
+        .map(|pair| (pair[0], pair.get(1).copied().unwrap_or(F::zero())))
+        .collect()
+}
+
+// fills the range slots of the whole trace
+pub fn populate_range_check<F: PrimeField32>(values: &mut [F]) {
+    for row in values.chunks_mut(BIN_OP_ROW_SIZE) {
+        let cols: &mut ProgExecCols<F> = row.borrow_mut();
+        let limbs = RANGE_CHECKED_OPS
+            .iter()
+            .find(|op| flag(cols, **op) == F::one())
+            .map_or(Vec::new(), |op| op_range_limbs(cols, *op));
+        assert!(limbs.len() <= RANGE_SLOTS);
+        for j in 0..RANGE_SLOTS {
+            cols.range_slot[j] = limbs.get(j).map_or(F::zero(), |&(col, offset)| {
+                col + F::from_canonical_u32(offset)
+            });
+        }
+        for (a, b) in range_pairs(cols) {
+            assert!(byte_table_row(a, b).is_some(), "limb out of the byte range");
+        }
+    }
+}
+
+// the byte table row of a, b if both are bytes
+fn byte_table_row<F: PrimeField32>(a: F, b: F) -> Option<usize> {
+    let (a, b) = (a.as_canonical_u32() as usize, b.as_canonical_u32() as usize);
+    (a < 256 && b < 256).then_some(a << 8 | b)
+}
+
+// The byte table is the preprocessed trace, the main trace holds the number of lookups of
+// every pair of bytes by opcode.
+#[derive(Default)]
+pub struct ByteChip;
+
+impl<F: Field> BaseAir<F> for ByteChip {
+    fn width(&self) -> usize {
+        NUM_BYTE_OPS
+    }
+}
+
+impl<F: PrimeField32> MachineAir<F> for ByteChip {
+    type Record = ProgExecRecord<F>;
+
+    type Program = ProgExec<F>;
+
+    fn name(&self) -> String {
+        "Byte".to_string()
+    }
+
+    fn generate_trace(
+        &self,
+        input: &Self::Record,
+        _output: &mut Self::Record,
+    ) -> RowMajorMatrix<F> {
+        let mut mult = vec![F::zero(); BYTE_TABLE_ROWS * NUM_BYTE_OPS];
+        let mut count = |opcode: ByteOpcode, a: F, b: F| {
+            // a limb out of the byte range has no row, its lookup is not balanced
+            if let Some(r) = byte_table_row(a, b) {
+                mult[r * NUM_BYTE_OPS + opcode as usize] += F::one();
+            }
+        };
+        for row in input.exec.chunks(BIN_OP_ROW_SIZE) {
+            let row: &ProgExecCols<F> = row.borrow();
+            for (a, b) in range_pairs(row) {
+                count(ByteOpcode::U8Range, a, b);
+            }
+            if flag(row, I64MathOps::Xor) == F::one() {
+                for i in 0..8 {
+                    count(ByteOpcode::Xor, row.left_arg[i], row.right_arg[i]);
+                }
+            }
+            if row.fp_mem_operand == F::one() {
+                for lane in 0..2 {
+                    let mem = &row.fp_lane(lane).fdiv().mem;
+                    for i in 0..3 {
+                        count(ByteOpcode::Xor, mem.mant[i], mem.mask[i]);
+                    }
+                }
+            }
+        }
+        RowMajorMatrix::new(mult, NUM_BYTE_OPS)
+    }
+
+    fn included(&self, _shard: &Self::Record) -> bool {
+        true
+    }
+
+    fn preprocessed_width(&self) -> usize {
+        BYTE_TABLE_WIDTH
+    }
+
+    fn generate_preprocessed_trace(&self, _program: &Self::Program) -> Option<RowMajorMatrix<F>> {
+        let mut values = Vec::with_capacity(BYTE_TABLE_ROWS * BYTE_TABLE_WIDTH);
+        for a in 0..256u32 {
+            for b in 0..256u32 {
+                values.extend([a, b, a ^ b].map(F::from_canonical_u32));
+            }
+        }
+        Some(RowMajorMatrix::new(values, BYTE_TABLE_WIDTH))
+    }
+}
+
+impl<AB: SP1AirBuilder + PairBuilder> Air<AB> for ByteChip {
+    fn eval(&self, builder: &mut AB) {
+        let main = builder.main();
+        let preprocessed = builder.preprocessed();
+        let mult = main.row_slice(0);
+        let table = preprocessed.row_slice(0);
+        for (opcode, c) in [
+            (ByteOpcode::U8Range, AB::Expr::zero()),
+            (ByteOpcode::Xor, table[BYTE_XOR].into()),
+        ] {
+            let values = vec![
+                AB::Expr::from_canonical_u8(opcode as u8),
+                table[BYTE_A].into(),
+                table[BYTE_B].into(),
+                c,
+            ];
+            builder.receive(
+                AirInteraction::new(values, mult[opcode as usize].into(), InteractionKind::Byte),
+                InteractionScope::Local,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use p3_baby_bear::BabyBear;
+
+    use super::*;
+    use crate::debug_builder::{count_failures, row_mut, run_program, test_regs};
+    use crate::math_ops::{add_op, mulh_op};
+
+    // a cheating prover copies its forged op limbs to the range slots
+    fn fill_range_slots(cols: &mut ProgExecCols<BabyBear>, op: I64MathOps) {
+        for (j, (col, offset)) in op_range_limbs(cols, op).into_iter().enumerate() {
+            cols.range_slot[j] = col + BabyBear::from_canonical_u32(offset);
+        }
+    }
+
+    #[test]
+    fn range_check_rejects_out_of_range_limbs() {
+        let limb = BabyBear::from_canonical_u32(256);
+
+        // 0xff + 0x01 carries into the second result limb, the same sum holds with a
+        // 9-bit low result limb and no carry
+        let (prog, trace, public_values) = run_program(vec![add_op()], test_regs(0xff, 0x01), 1);
+        assert_eq!(count_failures(&prog, &trace, &public_values), 0);
+        let mut tampered = trace.clone();
+        let cols = row_mut(&mut tampered, 1);
+        assert_eq!(cols.carry[0], BabyBear::one());
+        cols.result[0] += limb;
+        cols.carry[0] = BabyBear::zero();
+        cols.result[1] -= BabyBear::one();
+        assert!(count_failures(&prog, &tampered, &public_values) > 0);
+
+        // 0xff * 0xff is 0xfe01, the same product holds with a 9-bit low product limb and
+        // a smaller carry into the second limb
+        let (prog, trace, public_values) = run_program(vec![mulh_op()], test_regs(0xff, 0xff), 1);
+        assert_eq!(count_failures(&prog, &trace, &public_values), 0);
+        let mut tampered = trace.clone();
+        let cols = row_mut(&mut tampered, 1);
+        let mul = cols.mul_mut();
+        mul.product[0] += limb;
+        mul.carry[0] -= BabyBear::one();
+        mul.product[1] -= BabyBear::one();
+        fill_range_slots(cols, I64MathOps::MulH);
+        assert!(count_failures(&prog, &tampered, &public_values) > 0);
+
+        // a range slot that is not its limb
+        let mut tampered = trace.clone();
+        row_mut(&mut tampered, 1).range_slot[0] += BabyBear::one();
+        assert!(count_failures(&prog, &tampered, &public_values) > 0);
+    }
+}
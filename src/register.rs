@@ -1,17 +1,151 @@
+use std::borrow::Borrow;
+
+use p3_air::{AirBuilder, AirBuilderWithPublicValues};
+use p3_field::AbstractField;
+use p3_field::Field;
+use p3_matrix::Matrix;
+
+use crate::math_ops::{flag, I64MathOps};
+use crate::scratchpad::{pack_value, pack_value_u64, Scratchpad};
+use crate::stark_primitives::{ProgExecCols, FP_REGS, INT_REGS, REG_FILE_SIZE};
+
+// RandomX F, E and A groups have 4 registers each
+pub const FP_REGS_NUM: usize = 4;
+
+// integer ops writing the result to the dst register
+pub const INT_WRITE_OPS: [I64MathOps; 13] = [
+    I64MathOps::Add,
+    I64MathOps::Sub,
+    I64MathOps::Mul,
+    I64MathOps::MulH,
+    I64MathOps::SMulH,
+    I64MathOps::MulRcp,
+    I64MathOps::Xor,
+    I64MathOps::Ror,
+    I64MathOps::Rol,
+    I64MathOps::Neg,
+    I64MathOps::Swap,
+    I64MathOps::AddRs,
+    I64MathOps::CBranch,
+];
+
+// integer ops taking the src register as the right operand without an imm32 form
+pub const SRC_OPERAND_OPS: [I64MathOps; 4] = [
+    I64MathOps::MulH,
+    I64MathOps::SMulH,
+    I64MathOps::Swap,
+    I64MathOps::AddRs,
+];
+
+// FP ops, they read the left register and write the result register of their group
+pub const FP_OPS: [I64MathOps; 7] = [
+    I64MathOps::FAdd,
+    I64MathOps::FSub,
+    I64MathOps::FMul,
+    I64MathOps::FDiv,
+    I64MathOps::FSqrt,
+    I64MathOps::FScal,
+    I64MathOps::FSwap,
+];
+
+// FP ops working on an E group register
+pub const FP_E_OPS: [I64MathOps; 3] = [I64MathOps::FMul, I64MathOps::FDiv, I64MathOps::FSqrt];
+
+// FP ops taking the right operand from an A group register, FDiv might load it instead
+pub const FP_RIGHT_OPS: [I64MathOps; 4] = [
+    I64MathOps::FAdd,
+    I64MathOps::FSub,
+    I64MathOps::FMul,
+    I64MathOps::FDiv,
+];
+
+fn is_one_of(ops: &[I64MathOps], op: I64MathOps) -> bool {
+    ops.iter().any(|o| o.flag_idx() == op.flag_idx())
+}
+
 #[derive(Clone, Debug)]
 pub struct RegFile {
     pub int_regs: Vec<i64>,
+    // RandomX FP rounding mode set by CFROUND
+    pub fprc: u32,
+    // every FP register holds a pair of doubles
+    pub f_regs: Vec<[f64; 2]>,
+    pub e_regs: Vec<[f64; 2]>,
+    // read-only
+    pub a_regs: Vec<[f64; 2]>,
     pub cnt: u32,
+    pub pc: u32,
+    pub scratchpad: Scratchpad,
 }
 
 impl RegFile {
     pub fn new(reg_file_size: usize) -> Self {
+        assert!(
+            reg_file_size <= INT_REGS,
+            "the trace holds {} registers",
+            INT_REGS
+        );
         let int_regs = vec![0; reg_file_size];
+        let fprc = 0;
+        let f_regs = vec![[0.0; 2]; FP_REGS_NUM];
+        let e_regs = vec![[0.0; 2]; FP_REGS_NUM];
+        let a_regs = vec![[0.0; 2]; FP_REGS_NUM];
         let cnt = 0;
-        RegFile { int_regs, cnt }
+        let pc = 0;
+        let scratchpad = Scratchpad::new();
+        RegFile {
+            int_regs,
+            fprc,
+            f_regs,
+            e_regs,
+            a_regs,
+            cnt,
+            pc,
+            scratchpad,
+        }
+    }
+
+    // F registers followed by E registers like the FSWAP_R destination index
+    pub fn fp_reg(&self, idx: usize) -> [f64; 2] {
+        if idx < FP_REGS_NUM {
+            self.f_regs[idx]
+        } else {
+            self.e_regs[idx - FP_REGS_NUM]
+        }
+    }
+
+    pub fn set_fp_reg(&mut self, idx: usize, value: [f64; 2]) {
+        if idx < FP_REGS_NUM {
+            self.f_regs[idx] = value;
+        } else {
+            self.e_regs[idx - FP_REGS_NUM] = value;
+        }
+    }
+
+    // the register file columns, the integer registers past the reg file size are 0
+    pub fn packed<F: Field>(&self) -> Vec<F> {
+        let mut packed = Vec::with_capacity(REG_FILE_SIZE);
+        for i in 0..INT_REGS {
+            let value = self.int_regs.get(i).copied().unwrap_or(0);
+            packed.extend(pack_value_u64(value as u64).map(F::from_canonical_u32));
+        }
+        for reg in self.f_regs.iter().chain(&self.e_regs).chain(&self.a_regs) {
+            for lane in reg {
+                packed.extend(pack_value_u64(lane.to_bits()).map(F::from_canonical_u32));
+            }
+        }
+        packed
+    }
+
+    // the machine state: the packed register file followed by the rounding mode
+    pub fn state<F: Field>(&self) -> Vec<F> {
+        let mut state = self.packed();
+        state.push(F::from_canonical_u32(self.fprc));
+        state
     }
 }
 
+// The initial register file is a public input, the first trace row must hold it.
 pub fn init_regs(regs_num: usize) -> RegFile {
     let mut regs = RegFile::new(regs_num);
     for i in 0..regs_num {
@@ -27,5 +161,206 @@ pub fn init_regs(regs_num: usize) -> RegFile {
             8 * mul,
         ]);
     }
+    // FP ops expect normal doubles
+    for i in 0..FP_REGS_NUM {
+        let x = (i + 1) as f64;
+        regs.f_regs[i] = [1.1 * x, -2.3 * x];
+        regs.e_regs[i] = [3.7 * x, 0.9 * x];
+        regs.a_regs[i] = [1.0 + x / 7.0, 2.0 + x / 3.0];
+    }
     regs
-}
\ No newline at end of file
+}
+// Every row holds the register file before its op, the first row holds the initial one.
+// The op reads its operands from the selected registers and the next row holds
+// the register file with the result written to the selected result register.
+pub fn eval_reg_file<AB: AirBuilder + AirBuilderWithPublicValues>(builder: &mut AB) {
+    let main = builder.main();
+    let local = main.row_slice(0);
+    let next = main.row_slice(1);
+    let local: &ProgExecCols<AB::Var> = (*local).borrow();
+    let next: &ProgExecCols<AB::Var> = (*next).borrow();
+
+    let flags_sum = |ops: &[I64MathOps]| {
+        ops.iter()
+            .fold(AB::Expr::zero(), |sum, op| sum + flag(local, *op))
+    };
+    let int_write = flags_sum(&INT_WRITE_OPS);
+    let int_left =
+        int_write.clone() + flag(local, I64MathOps::CFRound) + flag(local, I64MathOps::IStore);
+    let is_swap: AB::Expr = flag(local, I64MathOps::Swap).into();
+    let is_fp = flags_sum(&FP_OPS);
+    let is_e_group = flags_sum(&FP_E_OPS);
+    let fp_right = flags_sum(&FP_RIGHT_OPS);
+    let src_operand = flags_sum(&SRC_OPERAND_OPS) * (AB::Expr::one() - local.mem_operand);
+    // FDiv loading its divisor takes the address base from an integer register
+    let fp_mem = local.fp_mem_operand;
+    let fp_right_reg = fp_right.clone() - fp_mem;
+    let not_first_row = AB::Expr::one() - builder.is_first_row();
+    let is_int = not_first_row.clone() - is_fp.clone();
+
+    // the selected register is the index within the F, E or A group for the FP ops
+    let mut sel_idx = vec![];
+    for sel in [
+        &local.reg_left_sel,
+        &local.reg_right_sel,
+        &local.reg_res_sel,
+    ] {
+        let mut sel_sum = AB::Expr::zero();
+        let mut idx = AB::Expr::zero();
+        for j in 0..FP_REGS {
+            builder.assert_bool(sel[j]);
+            sel_sum = sel_sum + sel[j];
+            idx = idx + sel[j] * AB::F::from_canonical_usize(j);
+        }
+        builder.assert_eq(sel_sum, not_first_row.clone());
+        // the integer ops and NoOp select among the integer registers only
+        for j in INT_REGS..FP_REGS {
+            builder.when(is_int.clone()).assert_zero(sel[j]);
+        }
+        sel_idx.push(idx);
+    }
+    let group = AB::F::from_canonical_usize(FP_REGS_NUM);
+    builder.assert_eq(
+        sel_idx[0].clone(),
+        local.left_reg_idx + is_e_group.clone() * group,
+    );
+    builder.assert_eq(
+        sel_idx[1].clone(),
+        local.right_reg_idx + fp_right_reg.clone() * group * AB::F::two(),
+    );
+    builder.assert_eq(sel_idx[2].clone(), local.res_reg_idx + is_e_group * group);
+
+    let left = pack_value::<AB>(&local.left_arg);
+    let right = pack_value::<AB>(&local.right_arg);
+    let res = pack_value::<AB>(&local.result);
+    let src = pack_value::<AB>(&local.src_val);
+    let lanes = |lo: &[AB::Var; 8], hi: &[AB::Var; 8]| -> Vec<AB::Expr> {
+        let mut packed = pack_value::<AB>(lo).to_vec();
+        packed.extend(pack_value::<AB>(hi));
+        packed
+    };
+    let fp_left = lanes(&local.left_arg, &local.left_arg_hi);
+    let fp_right_arg = lanes(&local.right_arg, &local.right_arg_hi);
+    let fp_res = lanes(&local.result, &local.result_hi);
+
+    // src_val is the src register of every integer op and NoOp
+    for k in 0..3 {
+        let mut left_reg = AB::Expr::zero();
+        let mut src_reg = AB::Expr::zero();
+        for j in 0..INT_REGS {
+            left_reg = left_reg + local.reg_left_sel[j] * local.reg_int[3 * j + k];
+            src_reg = src_reg + local.reg_right_sel[j] * local.reg_int[3 * j + k];
+        }
+        builder
+            .when(int_left.clone())
+            .assert_eq(left[k].clone(), left_reg);
+        builder
+            .when(is_int.clone() + fp_mem)
+            .assert_eq(src[k].clone(), src_reg);
+        builder
+            .when(src_operand.clone())
+            .assert_eq(right[k].clone(), src[k].clone());
+    }
+    for k in 0..6 {
+        let mut left_reg = AB::Expr::zero();
+        let mut right_reg = AB::Expr::zero();
+        for j in 0..FP_REGS {
+            left_reg = left_reg + local.reg_left_sel[j] * local.reg_fp[6 * j + k];
+            right_reg = right_reg + local.reg_right_sel[j] * local.reg_fp[6 * j + k];
+        }
+        builder
+            .when(is_fp.clone())
+            .assert_eq(fp_left[k].clone(), left_reg);
+        builder
+            .when(fp_right_reg.clone())
+            .assert_eq(fp_right_arg[k].clone(), right_reg);
+    }
+
+    // Swap also writes the left operand to the src register
+    let mut when_transition = builder.when_transition();
+    for j in 0..INT_REGS {
+        for k in 0..3 {
+            let reg = local.reg_int[3 * j + k];
+            let write = local.reg_res_sel[j] * int_write.clone() * (res[k].clone() - reg)
+                + local.reg_right_sel[j] * is_swap.clone() * (left[k].clone() - reg);
+            when_transition.assert_eq(next.reg_int[3 * j + k], reg + write);
+        }
+    }
+    // the A group registers are read only
+    for j in 0..FP_REGS {
+        for k in 0..6 {
+            let reg = local.reg_fp[6 * j + k];
+            let mut updated: AB::Expr = reg.into();
+            if j < 2 * FP_REGS_NUM {
+                updated =
+                    updated + local.reg_res_sel[j] * is_fp.clone() * (fp_res[k].clone() - reg);
+            }
+            when_transition.assert_eq(next.reg_fp[6 * j + k], updated);
+        }
+    }
+}
+
+// packed is the register file as RegFile::packed lays it out
+pub fn populate_reg_file<F: Field>(cols: &mut ProgExecCols<F>, packed: &[F]) {
+    let (int, fp) = packed.split_at(3 * INT_REGS);
+    cols.reg_int.copy_from_slice(int);
+    cols.reg_fp.copy_from_slice(fp);
+}
+
+// the FP ops select the F, E or A group registers after the integer ones
+pub fn populate_reg_sel<F: Field>(
+    cols: &mut ProgExecCols<F>,
+    op: I64MathOps,
+    left_idx: usize,
+    right_idx: usize,
+    res_idx: usize,
+    mem_operand: bool,
+) {
+    let group = if is_one_of(&FP_E_OPS, op) {
+        FP_REGS_NUM
+    } else {
+        0
+    };
+    let right_group = if is_one_of(&FP_RIGHT_OPS, op) && !mem_operand {
+        2 * FP_REGS_NUM
+    } else {
+        0
+    };
+    cols.left_reg_idx = F::from_canonical_usize(left_idx);
+    cols.reg_left_sel[left_idx + group] = F::one();
+    cols.reg_right_sel[right_idx + right_group] = F::one();
+    cols.reg_res_sel[res_idx + group] = F::one();
+}
+
+#[cfg(test)]
+mod tests {
+    use p3_baby_bear::BabyBear;
+    use p3_field::AbstractField;
+
+    use super::*;
+    use crate::debug_builder::{count_failures, row, row_mut, run_program, test_regs};
+    use crate::math_ops::{add_op, fadd_op, mul_op, xor_op};
+
+    #[test]
+    fn reg_file_rejects_broken_chains() {
+        let ops = vec![add_op(), fadd_op(), xor_op(), mul_op()];
+        let (prog, trace, public_values) = run_program(ops, test_regs(1000, 77), 2);
+        assert_eq!(count_failures(&prog, &trace, &public_values), 0);
+
+        // the last integer and FP registers are never written, they change between rows
+        let mut tampered = trace.clone();
+        row_mut(&mut tampered, 3).reg_int[3 * (INT_REGS - 1)] += BabyBear::one();
+        assert!(count_failures(&prog, &tampered, &public_values) > 0);
+        let mut tampered = trace.clone();
+        row_mut(&mut tampered, 5).reg_fp[6 * (FP_REGS - 1) + 4] += BabyBear::one();
+        assert!(count_failures(&prog, &tampered, &public_values) > 0);
+
+        // the result register keeps its value after the Add
+        let res = 3 * prog.ops[0].res_reg_idx as usize;
+        let before = row(&trace, 1).reg_int;
+        assert_ne!(before[res..res + 3], row(&trace, 2).reg_int[res..res + 3]);
+        let mut tampered = trace.clone();
+        row_mut(&mut tampered, 2).reg_int[res..res + 3].copy_from_slice(&before[res..res + 3]);
+        assert!(count_failures(&prog, &tampered, &public_values) > 0);
+    }
+}
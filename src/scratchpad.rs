@@ -0,0 +1,500 @@
+use std::borrow::{Borrow, BorrowMut};
+use std::fmt::Debug;
+
+use p3_air::{AirBuilder, AirBuilderWithPublicValues};
+use p3_field::AbstractField;
+use p3_field::Field;
+use p3_matrix::Matrix;
+use sp1_stark::air::{AirInteraction, InteractionScope, SP1AirBuilder};
+use sp1_stark::InteractionKind;
+
+use crate::stark_primitives::{ProgExecCols, BIN_OP_ROW_SIZE};
+
+// RandomX scratchpad levels sizes in bytes
+pub const SCRATCHPAD_L1: u32 = 16 * 1024;
+pub const SCRATCHPAD_L2: u32 = 256 * 1024;
+pub const SCRATCHPAD_L3: u32 = 2 * 1024 * 1024;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub enum ScratchpadLevel {
+    L1,
+    L2,
+    #[default]
+    L3,
+}
+
+impl ScratchpadLevel {
+    pub fn size(&self) -> u32 {
+        match self {
+            ScratchpadLevel::L1 => SCRATCHPAD_L1,
+            ScratchpadLevel::L2 => SCRATCHPAD_L2,
+            ScratchpadLevel::L3 => SCRATCHPAD_L3,
+        }
+    }
+
+    // 8-byte aligned address within the level
+    pub fn mask(&self) -> u32 {
+        self.size() - 8
+    }
+
+    // position of the level among the level selectors
+    pub fn sel_idx(&self) -> usize {
+        *self as usize
+    }
+
+    // number of the low address bits kept by the mask
+    pub fn bits(&self) -> usize {
+        self.size().trailing_zeros() as usize
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MemAccess {
+    pub addr: u32,
+    // the row counter of the op
+    pub ts: u32,
+    pub is_write: bool,
+    pub value: u64,
+}
+
+// 2 MiB of zero initialized 64-bit words and the log of every access
+#[derive(Clone)]
+pub struct Scratchpad {
+    words: Vec<u64>,
+    pub log: Vec<MemAccess>,
+}
+
+impl Debug for Scratchpad {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Scratchpad")
+            .field("log_len", &self.log.len())
+            .finish()
+    }
+}
+
+impl Default for Scratchpad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scratchpad {
+    pub fn new() -> Self {
+        Scratchpad {
+            words: vec![0; (SCRATCHPAD_L3 / 8) as usize],
+            log: Vec::new(),
+        }
+    }
+
+    pub fn read(&mut self, addr: u32, ts: u32) -> u64 {
+        let value = self.words[(addr / 8) as usize];
+        self.log.push(MemAccess {
+            addr,
+            ts,
+            is_write: false,
+            value,
+        });
+        value
+    }
+
+    pub fn write(&mut self, addr: u32, ts: u32, value: u64) {
+        self.words[(addr / 8) as usize] = value;
+        self.log.push(MemAccess {
+            addr,
+            ts,
+            is_write: true,
+            value,
+        });
+    }
+}
+
+// base + sign extended imm32 masked by the scratchpad level
+pub fn mem_address(base: u64, imm32: u32, level: ScratchpadLevel) -> u32 {
+    (base.wrapping_add(imm32 as i32 as i64 as u64) as u32) & level.mask()
+}
+
+// the 8 value limbs packed into 24, 24 and 16 bits
+pub fn pack_value<AB: AirBuilder>(limbs: &[AB::Var]) -> [AB::Expr; 3] {
+    let mut packed = [AB::Expr::zero(), AB::Expr::zero(), AB::Expr::zero()];
+    for (i, limb) in limbs.iter().enumerate() {
+        packed[i / 3] =
+            packed[i / 3].clone() + *limb * AB::F::from_canonical_u32(1 << (8 * (i % 3)));
+    }
+    packed
+}
+
+pub fn pack_value_u64(value: u64) -> [u32; 3] {
+    [
+        (value & 0xff_ffff) as u32,
+        ((value >> 24) & 0xff_ffff) as u32,
+        (value >> 48) as u32,
+    ]
+}
+
+// Asserts mem_addr is the base limbs + imm32 masked by the scratchpad level. Only the low
+// 24 bits of the sum are computed as the largest mask has 21 bits.
+pub fn eval_mem_address<AB: AirBuilder + AirBuilderWithPublicValues>(
+    builder: &mut AB,
+    local: &ProgExecCols<AB::Var>,
+    is_real: AB::Expr,
+    base: &[AB::Expr],
+) {
+    let base_limb = AB::F::from_canonical_u32(256);
+    let mut when_is_real = builder.when(is_real);
+
+    let mut level_sel_sum = AB::Expr::zero();
+    for k in 0..3 {
+        when_is_real.assert_bool(local.mem_level_sel[k]);
+        level_sel_sum = level_sel_sum + local.mem_level_sel[k];
+    }
+    when_is_real.assert_one(level_sel_sum);
+
+    let mut prev_carry = AB::Expr::zero();
+    for i in 0..3 {
+        let mut limb = AB::Expr::zero();
+        for j in 0..8 {
+            let bit = local.mem_addr_bits[8 * i + j];
+            when_is_real.assert_bool(bit);
+            limb = limb + bit * AB::F::from_canonical_u32(1 << j);
+        }
+        let carry = local.mem_addr_carry[i];
+        when_is_real.assert_bool(carry);
+        when_is_real.assert_eq(
+            base[i].clone() + local.imm[i] + prev_carry,
+            limb + carry * base_limb,
+        );
+        prev_carry = carry.into();
+    }
+
+    // the bit j is kept by the levels with more than j address bits
+    let levels = [
+        ScratchpadLevel::L1,
+        ScratchpadLevel::L2,
+        ScratchpadLevel::L3,
+    ];
+    let mut addr = AB::Expr::zero();
+    for j in 3..24 {
+        let mut kept = AB::Expr::zero();
+        for level in levels {
+            if j < level.bits() {
+                kept = kept + local.mem_level_sel[level.sel_idx()];
+            }
+        }
+        addr = addr + local.mem_addr_bits[j] * kept * AB::F::from_canonical_u32(1 << j);
+    }
+    when_is_real.assert_eq(local.mem_addr, addr);
+}
+
+// Memory consistency. The sorted log is a permutation of the accesses: every access is
+// sent over the memory bus and every sorted access receives it, the first row is a zero
+// sentinel. Sorted accesses to the same address have increasing times, a read returns
+// the previous value of the address and the first read of an address returns 0.
+pub fn eval_memory<AB: SP1AirBuilder>(builder: &mut AB) {
+    let main = builder.main();
+    let local = main.row_slice(0);
+    let next = main.row_slice(1);
+    let local: &ProgExecCols<AB::Var> = (*local).borrow();
+    let next: &ProgExecCols<AB::Var> = (*next).borrow();
+
+    let one = AB::Expr::one();
+
+    for col in [
+        local.mem_access,
+        local.mem_write,
+        local.mem_sorted_access,
+        local.mem_sorted_write,
+    ] {
+        builder.assert_bool(col);
+    }
+
+    let access =
+        |addr: AB::Var, ts: AB::Var, write: AB::Var, val: &[AB::Var; 3]| -> Vec<AB::Expr> {
+            vec![
+                addr.into(),
+                ts.into(),
+                write.into(),
+                val[0].into(),
+                val[1].into(),
+                val[2].into(),
+            ]
+        };
+    builder.send(
+        AirInteraction::new(
+            access(local.mem_addr, local.cnt, local.mem_write, &local.mem_val),
+            local.mem_access.into(),
+            InteractionKind::Memory,
+        ),
+        InteractionScope::Local,
+    );
+    builder.receive(
+        AirInteraction::new(
+            access(
+                local.mem_sorted_addr,
+                local.mem_sorted_ts,
+                local.mem_sorted_write,
+                &local.mem_sorted_val,
+            ),
+            local.mem_sorted_access.into(),
+            InteractionKind::Memory,
+        ),
+        InteractionScope::Local,
+    );
+
+    let mut when_first_row = builder.when_first_row();
+    for col in [
+        local.mem_sorted_access,
+        local.mem_sorted_write,
+        local.mem_sorted_addr,
+        local.mem_sorted_ts,
+        local.mem_sorted_val[0],
+        local.mem_sorted_val[1],
+        local.mem_sorted_val[2],
+    ] {
+        when_first_row.assert_zero(col);
+    }
+
+    // the sorted accesses are followed by rows without accesses
+    let not_first_row = one.clone() - builder.is_first_row();
+    builder.when_transition().assert_zero(
+        not_first_row * next.mem_sorted_access * (one.clone() - local.mem_sorted_access),
+    );
+
+    // is-equal gadget
+    let same = local.mem_same_addr;
+    let addr_diff = next.mem_sorted_addr - local.mem_sorted_addr;
+    let mut when_transition = builder.when_transition();
+    when_transition.assert_zero(same * addr_diff.clone());
+    when_transition.assert_one(same + addr_diff.clone() * local.mem_addr_diff_inv);
+    when_transition.assert_eq(
+        local.mem_same_read,
+        same * (one.clone() - next.mem_sorted_write),
+    );
+    when_transition.assert_eq(
+        local.mem_new_read,
+        (one.clone() - same) * (one.clone() - next.mem_sorted_write),
+    );
+
+    let ts_diff = next.mem_sorted_ts - local.mem_sorted_ts - AB::F::one();
+    let diff = ts_diff.clone() + (one - same) * (addr_diff - AB::F::one() - ts_diff);
+    let mut diff_bits = AB::Expr::zero();
+    for j in 0..24 {
+        when_transition.assert_bool(local.mem_diff_bits[j]);
+        diff_bits = diff_bits + local.mem_diff_bits[j] * AB::F::from_canonical_u32(1 << j);
+    }
+    let mut when_next_access = when_transition.when(next.mem_sorted_access);
+    when_next_access.assert_eq(diff, diff_bits);
+    for k in 0..3 {
+        let next_val = next.mem_sorted_val[k];
+        when_next_access.assert_zero(local.mem_same_read * (next_val - local.mem_sorted_val[k]));
+        when_next_access.assert_zero(local.mem_new_read * next_val);
+    }
+}
+
+pub fn populate_mem_access<F: Field>(
+    cols: &mut ProgExecCols<F>,
+    level: ScratchpadLevel,
+    base: u64,
+    imm32: u32,
+    addr: u32,
+    is_write: bool,
+    value: u64,
+) {
+    cols.mem_access = F::one();
+    cols.mem_write = F::from_bool(is_write);
+    cols.mem_addr = F::from_canonical_u32(addr);
+    cols.mem_val = pack_value_u64(value).map(F::from_canonical_u32);
+    cols.mem_level_sel[level.sel_idx()] = F::one();
+
+    let base_as_b = base.to_le_bytes();
+    let imm_as_b = imm32.to_le_bytes();
+    let sum = base.wrapping_add(imm32 as i32 as i64 as u64);
+    let mut carry = 0u32;
+    for i in 0..3 {
+        carry = (base_as_b[i] as u32 + imm_as_b[i] as u32 + carry) >> 8;
+        cols.mem_addr_carry[i] = F::from_canonical_u32(carry);
+    }
+    for j in 0..24 {
+        cols.mem_addr_bits[j] = F::from_canonical_u64((sum >> j) & 1);
+    }
+}
+
+// Fills the memory log columns of the whole trace from the access log.
+pub fn populate_memory_log<F: Field>(values: &mut [F], log: &[MemAccess]) {
+    let rows = values.len() / BIN_OP_ROW_SIZE;
+    assert!(log.len() < rows, "the sorted log follows the sentinel row");
+
+    let mut sorted = log.to_vec();
+    sorted.sort_by_key(|access| (access.addr, access.ts));
+    // the first row is a zero sentinel
+    let sentinel = MemAccess {
+        addr: 0,
+        ts: 0,
+        is_write: false,
+        value: 0,
+    };
+    let entry = |r: usize| -> Option<&MemAccess> {
+        if r == 0 {
+            Some(&sentinel)
+        } else {
+            sorted.get(r - 1)
+        }
+    };
+
+    for (row, access) in values.chunks_mut(BIN_OP_ROW_SIZE).skip(1).zip(&sorted) {
+        let cols: &mut ProgExecCols<F> = row.borrow_mut();
+        cols.mem_sorted_access = F::one();
+        cols.mem_sorted_write = F::from_bool(access.is_write);
+        cols.mem_sorted_addr = F::from_canonical_u32(access.addr);
+        cols.mem_sorted_ts = F::from_canonical_u32(access.ts);
+        cols.mem_sorted_val = pack_value_u64(access.value).map(F::from_canonical_u32);
+    }
+
+    for (r, row) in values
+        .chunks_mut(BIN_OP_ROW_SIZE)
+        .take(rows - 1)
+        .enumerate()
+    {
+        let cols: &mut ProgExecCols<F> = row.borrow_mut();
+        let (addr, ts) = entry(r).map_or((0, 0), |a| (a.addr, a.ts));
+        let next = entry(r + 1);
+        let (next_addr, next_write) = next.map_or((0, false), |a| (a.addr, a.is_write));
+        let same = addr == next_addr;
+        cols.mem_same_addr = F::from_bool(same);
+        cols.mem_addr_diff_inv = (F::from_canonical_u32(next_addr) - F::from_canonical_u32(addr))
+            .try_inverse()
+            .unwrap_or(F::zero());
+        cols.mem_same_read = F::from_bool(same && !next_write);
+        cols.mem_new_read = F::from_bool(!same && !next_write);
+        if let Some(next) = next {
+            let diff = if same {
+                next.ts - ts - 1
+            } else {
+                next.addr - addr - 1
+            };
+            for j in 0..24 {
+                cols.mem_diff_bits[j] = F::from_canonical_u32((diff >> j) & 1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use p3_baby_bear::BabyBear;
+    use p3_field::AbstractField;
+    use p3_matrix::dense::RowMajorMatrix;
+
+    use super::*;
+    use crate::debug_builder::{count_failures, row, row_mut, run_program, test_regs};
+    use crate::math_ops::{
+        add_op, fdiv_op, istore_op, mul_op, mulh_op, smulh_op, sub_op, xor_op, I64MathOp,
+    };
+
+    fn mem_ops() -> Vec<I64MathOp<BabyBear>> {
+        let mut istore_r0 = istore_op(ScratchpadLevel::L1, 0x3ff8);
+        istore_r0.right_reg_idx = 0;
+        vec![
+            istore_op(ScratchpadLevel::L1, 0x3ff8),
+            istore_op(ScratchpadLevel::L2, 0x8000_0010),
+            istore_op(ScratchpadLevel::L3, 0x1234_5678),
+            add_op().with_mem(ScratchpadLevel::L1, 0x3ff8),
+            sub_op().with_mem(ScratchpadLevel::L2, 0x8000_0010),
+            mul_op().with_mem(ScratchpadLevel::L3, 0x1234_5678),
+            istore_r0,
+            mulh_op().with_mem(ScratchpadLevel::L1, 0x0000_0010),
+            smulh_op().with_mem(ScratchpadLevel::L2, 0x7fff_fff8),
+            xor_op().with_mem(ScratchpadLevel::L3, 0xffff_fff0),
+            add_op()
+                .with_imm(0)
+                .with_mem(ScratchpadLevel::L1, 0x12_3458),
+            fdiv_op()
+                .with_mem(ScratchpadLevel::L1, 0x3ff8)
+                .with_e_mask([0x3f00_0000_0012_3456, 0x3e00_0000_0020_0001]),
+        ]
+    }
+
+    // some reads are of addresses never written, they return 0
+    #[test]
+    fn mem_ops_satisfy_constraints() {
+        for (r0, r1) in [
+            (0, 1),
+            (0x3ff8, -1),
+            (-0x1234_5678_9abc, 0x0123_4567_89ab_cdef),
+        ] {
+            let (prog, trace, public_values) = run_program(mem_ops(), test_regs(r0, r1), 2);
+            assert_eq!(
+                count_failures(&prog, &trace, &public_values),
+                0,
+                "r0 {} r1 {}",
+                r0,
+                r1
+            );
+        }
+    }
+
+    // the sorted log row of the access of the row r
+    fn sorted_row(trace: &RowMajorMatrix<BabyBear>, r: usize) -> usize {
+        let ts = row(trace, r).cnt;
+        (1..trace.height())
+            .find(|&s| {
+                let sorted = row(trace, s);
+                sorted.mem_sorted_access == BabyBear::one() && sorted.mem_sorted_ts == ts
+            })
+            .expect("every access is in the sorted log")
+    }
+
+    // The tampered accesses are changed in the sorted log as well, so the memory bus
+    // stays balanced and the sorted log or the address constraints must reject them.
+    #[test]
+    fn memory_rejects_inconsistent_accesses() {
+        // r0 = r1 so the Add loads the value the IStore wrote, the Xor loads an L2 address
+        // never written
+        let ops = vec![
+            istore_op(ScratchpadLevel::L1, 0x3ff8),
+            add_op().with_mem(ScratchpadLevel::L1, 0x3ff8),
+            xor_op().with_mem(ScratchpadLevel::L2, 0x8000_0010),
+        ];
+        let (prog, trace, public_values) = run_program(ops, test_regs(0x40, 0x40), 1);
+        assert_eq!(count_failures(&prog, &trace, &public_values), 0);
+        assert_eq!(row(&trace, 2).mem_val, row(&trace, 1).mem_val);
+        assert_eq!(row(&trace, 3).mem_val, [BabyBear::zero(); 3]);
+
+        // a load of another value than the stored one, and a load of an address never
+        // written that is not 0
+        for r in [2, 3] {
+            let mut tampered = trace.clone();
+            let s = sorted_row(&tampered, r);
+            row_mut(&mut tampered, r).mem_val[0] += BabyBear::one();
+            row_mut(&mut tampered, s).mem_sorted_val[0] += BabyBear::one();
+            assert!(count_failures(&prog, &tampered, &public_values) > 0);
+        }
+
+        // the store sorted after the load of its address
+        let mut tampered = trace.clone();
+        let (store, load) = (sorted_row(&trace, 1), sorted_row(&trace, 2));
+        let fields = |s| {
+            let cols = row(&trace, s);
+            (
+                cols.mem_sorted_write,
+                cols.mem_sorted_ts,
+                cols.mem_sorted_val,
+            )
+        };
+        for (s, (write, ts, val)) in [(store, fields(load)), (load, fields(store))] {
+            let cols = row_mut(&mut tampered, s);
+            cols.mem_sorted_write = write;
+            cols.mem_sorted_ts = ts;
+            cols.mem_sorted_val = val;
+        }
+        assert!(count_failures(&prog, &tampered, &public_values) > 0);
+
+        // an address that is not 8-byte aligned and one outside of the L1 level
+        for offset in [1, SCRATCHPAD_L1] {
+            let mut tampered = trace.clone();
+            let s = sorted_row(&tampered, 2);
+            row_mut(&mut tampered, 2).mem_addr += BabyBear::from_canonical_u32(offset);
+            row_mut(&mut tampered, s).mem_sorted_addr += BabyBear::from_canonical_u32(offset);
+            assert!(count_failures(&prog, &tampered, &public_values) > 0);
+        }
+    }
+}
@@ -1,9 +1,8 @@
+use std::borrow::{Borrow, BorrowMut};
+use std::mem::size_of;
 
 use p3_baby_bear::BabyBear;
 use p3_field::extension::BinomialExtensionField;
-use p3_uni_stark::{Proof, StarkConfig};
-
-use sp1_stark::{InnerChallenge, InnerChallenger, InnerPcs};
 
 pub(crate) type Val = BabyBear;
 pub(crate) type Challenge = BinomialExtensionField<Val, 4>;
@@ -41,15 +40,510 @@ pub(crate) type Challenge = BinomialExtensionField<Val, 4>;
 // pub type OuterPcs = TwoAdicFriPcs<Val, OuterDft, OuterValMmcs, OuterChallengeMmcs>;
 
 // pub(crate) type BabyBearKeccak = StarkConfig<Pcs, Challenge, Challenger>;
-pub(crate) type InnerBabyBearPoseidon2 = StarkConfig<InnerPcs, InnerChallenge, InnerChallenger>;
 // pub(crate) type BabyBearPoseidon2 = StarkConfig<OuterPcs, Challenge, OuterChallenger>;
 
-pub type P3Proof = Proof<InnerBabyBearPoseidon2>;
+// Columns of a ProgExec row. The first row is not an op: it holds the public values
+// hash in place of the operands, see MathOpFirstRow.
+#[repr(C)]
+pub struct ProgExecCols<T> {
+    // instruction counter
+    pub cnt: T,
+    pub flags: [T; NUM_FLAGS],
+    pub left_arg: [T; 8],
+    pub right_arg: [T; 8],
+    pub result: [T; 8],
+    pub carry: [T; CARRY],
+    pub imm: [T; 4],
+    pub imm_sign: T,
+    // imm32 top limb w/o sign
+    pub imm_low7: T,
+    // right operand is imm32 if its register is the result register
+    pub right_reg_idx: T,
+    pub res_reg_idx: T,
+    pub idx_eq: T,
+    pub idx_diff_inv: T,
+    // the right operand is either the src register or imm32
+    pub reg_operand: T,
+    pub use_imm: T,
+    pub src_val: [T; 8],
+    // program counter, the sequential next pc wraps to 0 after the last op of the program,
+    // pc_end is looked up with the instruction
+    pub pc: T,
+    pub next_pc: T,
+    pub seq_pc: T,
+    pub pc_end: T,
+    pub taken: T,
+    // the row is an executed op, the padding NoOps follow the real rows
+    pub is_real: T,
+    // the upper 64-bit lanes of the 128-bit FP operands
+    pub left_arg_hi: [T; 8],
+    pub right_arg_hi: [T; 8],
+    pub result_hi: [T; 8],
+    // one-hot encoded FP rounding mode in effect for the op
+    pub fprc_sel: [T; 4],
+    // Register file before the op: the integer registers followed by the F, E and A group
+    // registers of 2 lanes, every value is packed into 24, 24 and 16 bits
+    pub reg_int: [T; 3 * INT_REGS],
+    pub reg_fp: [T; 6 * FP_REGS],
+    pub left_reg_idx: T,
+    // one-hot selectors of the left, right and result registers among the integer registers
+    // or the FP registers for the FP ops
+    pub reg_left_sel: [T; FP_REGS],
+    pub reg_right_sel: [T; FP_REGS],
+    pub reg_res_sel: [T; FP_REGS],
+    // scratchpad access of the op, the value is packed into 24, 24 and 16 bits
+    pub mem_access: T,
+    pub mem_write: T,
+    // the right operand is loaded from the scratchpad
+    pub mem_operand: T,
+    // FDiv loads the int32 pair of its divisor lanes from the scratchpad
+    pub fp_mem_operand: T,
+    pub mem_addr: T,
+    pub mem_val: [T; 3],
+    // bits of the low 24 bits of base + imm32 and the carries of their limbs
+    pub mem_addr_bits: [T; 24],
+    pub mem_addr_carry: [T; 3],
+    // one-hot encoded L1, L2, L3 scratchpad level of the address mask
+    pub mem_level_sel: [T; 3],
+    // Memory log: the accesses sorted by address and time starting at the second row
+    pub mem_sorted_access: T,
+    pub mem_sorted_write: T,
+    pub mem_sorted_addr: T,
+    pub mem_sorted_ts: T,
+    pub mem_sorted_val: [T; 3],
+    // the next sorted access has the same address, it is a read of the same or of a new address
+    pub mem_same_addr: T,
+    pub mem_addr_diff_inv: T,
+    pub mem_same_read: T,
+    pub mem_new_read: T,
+    // bits of the next sorted time or address difference - 1
+    pub mem_diff_bits: [T; 24],
+    // Byte range check: the op specific limbs of the row, looked up in the byte table
+    pub range_slot: [T; RANGE_SLOTS],
+    // Program lookup: the AddRs shift, the CBranch condition and target and the E masks
+    // of an FDiv memory operand, 0 for the other ops, looked up with the other
+    // instruction words
+    pub prog_shift: T,
+    pub prog_cond: T,
+    pub prog_target: T,
+    pub prog_e_mask: [T; 2],
+    // Op specific columns. Their meaning depends on the op flag set in the row,
+    // so every op reuses the same area.
+    pub aux: [T; AUX_SIZE],
+}
 
-// 1 instr cnt + 10 ops flags + 8 arg1 + 8 arg2 + 8 res + 7 carry
-pub(crate) const BIN_OP_ROW_SIZE: usize = 42;
+pub(crate) const NUM_PROG_EXEC_COLS: usize = size_of::<ProgExecCols<u8>>();
+pub(crate) const BIN_OP_ROW_SIZE: usize = NUM_PROG_EXEC_COLS;
 pub(crate) const CARRY: usize = 7;
-pub(crate) const LEFT_ARG: usize = 11;
-pub(crate) const RIGHT_ARG: usize = 19;
-pub(crate) const RESULT: usize = 27;
-pub(crate) const CARRY_START: usize = 35;
+pub(crate) const NUM_FLAGS: usize = 32;
+pub(crate) const INT_REGS: usize = 8;
+pub(crate) const FP_REGS: usize = 12;
+pub(crate) const REG_FILE_SIZE: usize = 3 * INT_REGS + 6 * FP_REGS;
+pub(crate) const RANGE_SLOTS: usize = 186;
+pub(crate) const PROG_WORDS: usize = 13;
+pub(crate) const AUX_SIZE: usize = 2 * NUM_FP_LANE_COLS;
+
+// Borrow and BorrowMut of a column struct from a slice of its size
+macro_rules! impl_borrow {
+    ($cols:ident, $num_cols:expr) => {
+        impl<T> Borrow<$cols<T>> for [T] {
+            fn borrow(&self) -> &$cols<T> {
+                debug_assert_eq!(self.len(), $num_cols);
+                let (prefix, shorts, suffix) = unsafe { self.align_to::<$cols<T>>() };
+                debug_assert!(prefix.is_empty(), "Alignment should match");
+                debug_assert!(suffix.is_empty(), "Alignment should match");
+                debug_assert_eq!(shorts.len(), 1);
+                &shorts[0]
+            }
+        }
+
+        impl<T> BorrowMut<$cols<T>> for [T] {
+            fn borrow_mut(&mut self) -> &mut $cols<T> {
+                debug_assert_eq!(self.len(), $num_cols);
+                let (prefix, shorts, suffix) = unsafe { self.align_to_mut::<$cols<T>>() };
+                debug_assert!(prefix.is_empty(), "Alignment should match");
+                debug_assert!(suffix.is_empty(), "Alignment should match");
+                debug_assert_eq!(shorts.len(), 1);
+                &mut shorts[0]
+            }
+        }
+    };
+}
+
+impl_borrow!(ProgExecCols, NUM_PROG_EXEC_COLS);
+
+// Mul, MulH, SMulH and MulRcp aux columns
+#[repr(C)]
+pub struct MulCols<T> {
+    // 16 limbs of the unsigned 128-bit product
+    pub product: [T; 16],
+    // carries of the limb partial products sums, every carry is split into lo and hi bytes
+    pub carry: [T; 32],
+    // operands signs and top limbs w/o sign
+    pub left_sign: T,
+    pub right_sign: T,
+    pub left_low7: T,
+    pub right_low7: T,
+    // SMulH: carries of res + left_sign * right + right_sign * left
+    pub smulh_carry: [T; 8],
+    // MulRcp: imm32 * rcp + rem = 2^e where rcp is the right operand
+    pub rcp_rem: [T; 4],
+    // imm32 - rem - 1 and its carries
+    pub rcp_rem_diff: [T; 4],
+    pub rcp_rem_carry: [T; 3],
+    // rcp top limb w/o the top bit
+    pub rcp_top: T,
+    // carries of imm32 * rcp + rem limbs sums split into lo and hi bytes
+    pub rcp_carry: [T; 24],
+    // one-hot encoded e - 63, e is in 63..=95
+    pub rcp_pow: [T; RCP_POW_BITS],
+}
+
+pub(crate) const RCP_POW_BITS: usize = 33;
+pub(crate) const NUM_MUL_COLS: usize = size_of::<MulCols<u8>>();
+impl_borrow!(MulCols, NUM_MUL_COLS);
+
+// Ror, Rol and CFRound aux columns
+#[repr(C)]
+pub struct RotateCols<T> {
+    // bits of the lowest right operand limb, the shift amount is its 6 low bits
+    pub shift_bits: [T; 8],
+    // Rol rotates right by 64 - shift, the flag is set if the shift is not zero
+    pub wrap: T,
+    // one-hot encoded rotation by bytes and by bits within a byte
+    pub byte_sel: [T; 8],
+    pub bit_sel: [T; 8],
+    // left operand rotated by bytes
+    pub bytes: [T; 8],
+    // rotated bytes split into the parts that stay in and leave the byte
+    pub hi: [T; 8],
+    pub lo: [T; 8],
+    // CFRound: bits of the lowest rotated limb, the 2 low bits are the new rounding mode
+    pub cfr_bits: [T; 8],
+}
+
+pub(crate) const NUM_ROTATE_COLS: usize = size_of::<RotateCols<u8>>();
+impl_borrow!(RotateCols, NUM_ROTATE_COLS);
+
+// Swap aux columns
+#[repr(C)]
+pub struct SwapCols<T> {
+    // the value written into the right operand register
+    pub res: [T; 8],
+}
+
+pub(crate) const NUM_SWAP_COLS: usize = size_of::<SwapCols<u8>>();
+impl_borrow!(SwapCols, NUM_SWAP_COLS);
+
+// AddRs aux columns
+#[repr(C)]
+pub struct AddRsCols<T> {
+    // one-hot encoded shift 0..=3 of the right operand
+    pub shift_sel: [T; 4],
+    // carry of the top limb, the lower limbs carries are in the carry columns
+    pub carry_top: T,
+}
+
+pub(crate) const NUM_ADD_RS_COLS: usize = size_of::<AddRsCols<u8>>();
+impl_borrow!(AddRsCols, NUM_ADD_RS_COLS);
+
+// CBranch aux columns
+#[repr(C)]
+pub struct CBranchCols<T> {
+    // carry of the top limb, the lower limbs carries are in the carry columns
+    pub carry_top: T,
+    // bits 8..32 of the result
+    pub bits: [T; 24],
+    // one-hot encoded condition
+    pub cond_sel: [T; 16],
+    // the masked bits sum and its inverse
+    pub masked: T,
+    pub masked_inv: T,
+}
+
+pub(crate) const NUM_CBRANCH_COLS: usize = size_of::<CBranchCols<u8>>();
+impl_borrow!(CBranchCols, NUM_CBRANCH_COLS);
+
+// FP ops aux columns of a 64-bit lane, the lane 0 is followed by the lane 1
+#[repr(C)]
+pub struct FpLaneCols<T> {
+    // bits of the top 2 limbs of the left, right and result doubles and
+    // the inverses of their exponent e and 2047 - e
+    pub left_bits: [T; 16],
+    pub left_inv: [T; 2],
+    pub right_bits: [T; 16],
+    pub right_inv: [T; 2],
+    pub res_bits: [T; 16],
+    pub res_inv: [T; 2],
+    // FP op specific columns
+    pub op: [T; NUM_FP_OP_COLS],
+}
+
+pub(crate) const NUM_FP_LANE_COLS: usize = size_of::<FpLaneCols<u8>>();
+impl_borrow!(FpLaneCols, NUM_FP_LANE_COLS);
+
+// FP rounding of a normalized 53-bit significand in the current rounding mode
+#[repr(C)]
+pub struct FpRoundCols<T> {
+    // bits of the limb below the significand, the top one is the guard bit
+    pub guard_bits: [T; 8],
+    // lowest significand bit and the rest of its limb
+    pub lsb: T,
+    pub lsb_half: T,
+    // sticky bit is set if any bit below the guard bit is set
+    pub sticky: T,
+    pub sticky_inv: T,
+    pub sticky_or_lsb: T,
+    // rounding increment
+    pub inc: T,
+    // low 4 bits of the top significand limb
+    pub top_bits: [T; 4],
+    // carries of the rounded significand and the exponent increment
+    pub res_carry: [T; 6],
+    pub ovf: T,
+    // round to nearest increment, the flag that the mode rounds away from zero for
+    // the result sign and the flag that the result is inexact
+    pub inc_nearest: T,
+    pub away: T,
+    pub inexact: T,
+}
+
+// FAdd, FSub lane columns
+#[repr(C)]
+pub struct FAddCols<T> {
+    // right sign flipped by FSub, the flag that magnitudes are subtracted
+    pub sign: T,
+    pub op_sub: T,
+    // |left| - |right| limbs and borrows, the last borrow swaps the operands into x and y
+    pub cmp: [T; 8],
+    pub borrow: [T; 8],
+    // x exponent - y exponent
+    pub exp_diff: T,
+    // 7 limbs of the y significand
+    pub mant_y: [T; 7],
+    // one-hot encoded byte and bit parts of the y alignment shift
+    pub align_q: [T; 8],
+    pub align_r: [T; 8],
+    // the shift is capped at 63, the exponents difference - 63 limbs for the capped shift
+    pub cap: T,
+    pub cap_diff: [T; 2],
+    // y significand limbs shifted by bits split into lo and hi bytes
+    pub align_lo: [T; 7],
+    pub align_hi: [T; 7],
+    // 16 limbs of y * 2^(64 - shift)
+    pub aligned: [T; 16],
+    // 16 limbs of x * 2^64 +- aligned y and its carries
+    pub sum: [T; 16],
+    pub sum_carry: [T; 15],
+    // one-hot encoded byte and bit parts of the normalization shift
+    pub norm_q: [T; 16],
+    pub norm_r: [T; 8],
+    // sum limbs shifted by bits split into lo and hi bytes
+    pub norm_lo: [T; 16],
+    pub norm_hi: [T; 16],
+    // 16 limbs of the normalized sum with the leading bit at 124
+    pub norm: [T; 16],
+    pub round: FpRoundCols<T>,
+    // the left and result doubles are zero or infinite
+    pub left_zero: T,
+    pub left_inf: T,
+    pub res_zero: T,
+    pub res_inf: T,
+    // the operands cancel exactly, the sum is zero
+    pub cancel: T,
+    // neither of the above, the result is the rounded sum
+    pub rounded: T,
+    // rounded 53-bit significand, the top limb w/o the implicit bit
+    pub rnd: [T; 7],
+    // biased exponent of the normalized sum before rounding, the flags that it is 2047
+    // or 2046 with the inverses of their differences
+    pub exp: T,
+    pub exp_max: T,
+    pub exp_max_inv: T,
+    pub exp_edge: T,
+    pub exp_edge_inv: T,
+    // the rounded exponent is 2047 or more, the rounded result overflows and the mode
+    // gives the largest double instead of inf
+    pub exp_above: T,
+    pub exp_ovf: T,
+    pub to_max: T,
+}
+
+// FAdd has the most op specific columns
+pub(crate) const NUM_FP_OP_COLS: usize = size_of::<FAddCols<u8>>();
+impl_borrow!(FAddCols, NUM_FP_OP_COLS);
+
+// FMul lane columns
+#[repr(C)]
+pub struct FMulCols<T> {
+    // 14 limbs of the significands product and its carries split into lo and hi bytes
+    pub product: [T; 14],
+    pub carry: [T; 26],
+    // bits 104 and 105 of the product, the latter is set if the product has 106 bits
+    pub top: [T; 2],
+    // product limbs shifted by 3 or 4 bits split into lo and hi bytes
+    pub lo: [T; 14],
+    pub hi: [T; 13],
+    pub round: FpRoundCols<T>,
+}
+
+pub(crate) const NUM_FMUL_COLS: usize = size_of::<FMulCols<u8>>();
+impl_borrow!(FMulCols, NUM_FMUL_COLS);
+
+// FDiv lane columns
+#[repr(C)]
+pub struct FDivCols<T> {
+    // left significand * 2^(61 - shift) = quotient * right significand + rem
+    pub quot: [T; 8],
+    pub rem: [T; 7],
+    // right significand - rem - 1 and its carries
+    pub rem_diff: [T; 7],
+    pub rem_carry: [T; 6],
+    // set if the left significand is not less than the right one
+    pub shift: T,
+    // left significand limbs shifted by 4 or 5 bits split into lo and hi bytes
+    pub lo: [T; 7],
+    pub hi: [T; 7],
+    // carries of quotient * right significand + rem limbs sums split into lo and hi bytes
+    pub carry: [T; 28],
+    pub round: FpRoundCols<T>,
+    pub mem: FDivMemCols<T>,
+}
+
+// FDiv memory operand lane columns
+#[repr(C)]
+pub struct FDivMemCols<T> {
+    // the loaded int32 limbs, its sign and top limb w/o the sign
+    pub int: [T; 4],
+    pub sign: T,
+    pub int_low7: T,
+    // limbs of |x| and the carries of |x| + x = 2^32 for a negative x
+    pub abs: [T; 4],
+    pub neg_carry: [T; 3],
+    // one-hot encoded position of the leading bit of |x|
+    pub pos: [T; 32],
+    // |x| limbs shifted by bits split into lo and hi bytes
+    pub lo: [T; 4],
+    pub hi: [T; 4],
+    // 52-bit mantissa of x as a double, the top limb holds 4 bits
+    pub mant: [T; 7],
+    // low 22 bits of the E mask split into limbs, the 4 variable exponent bits of the mask
+    // and the mantissa low limbs XOR the mask limbs
+    pub mask: [T; 3],
+    pub mask_exp: T,
+    pub xor: [T; 3],
+}
+
+pub(crate) const NUM_FDIV_COLS: usize = size_of::<FDivCols<u8>>();
+impl_borrow!(FDivCols, NUM_FDIV_COLS);
+
+// FSqrt lane columns
+#[repr(C)]
+pub struct FSqrtCols<T> {
+    // floor of the root of the left significand * 2^(68 + odd exponent) and its rem
+    pub root: [T; 8],
+    pub rem: [T; 8],
+    // 2 * root - rem and its carries split into positive and negative parts
+    pub rem_diff: [T; 8],
+    pub rem_carry_pos: [T; 7],
+    pub rem_carry_neg: [T; 7],
+    // left significand limbs shifted by 4 or 5 bits split into lo and hi bytes
+    pub lo: [T; 7],
+    pub hi: [T; 7],
+    // carries of root * root + rem limbs sums split into lo and hi bytes
+    pub carry: [T; 30],
+    pub round: FpRoundCols<T>,
+}
+
+pub(crate) const NUM_FSQRT_COLS: usize = size_of::<FSqrtCols<u8>>();
+impl_borrow!(FSqrtCols, NUM_FSQRT_COLS);
+
+const _: () = assert!(NUM_FMUL_COLS <= NUM_FP_OP_COLS);
+const _: () = assert!(NUM_FDIV_COLS <= NUM_FP_OP_COLS);
+const _: () = assert!(NUM_FSQRT_COLS <= NUM_FP_OP_COLS);
+
+// The op specific views of the aux columns
+impl<T> ProgExecCols<T> {
+    pub fn mul(&self) -> &MulCols<T> {
+        self.aux[..NUM_MUL_COLS].borrow()
+    }
+
+    pub fn mul_mut(&mut self) -> &mut MulCols<T> {
+        self.aux[..NUM_MUL_COLS].borrow_mut()
+    }
+
+    pub fn rotate(&self) -> &RotateCols<T> {
+        self.aux[..NUM_ROTATE_COLS].borrow()
+    }
+
+    pub fn rotate_mut(&mut self) -> &mut RotateCols<T> {
+        self.aux[..NUM_ROTATE_COLS].borrow_mut()
+    }
+
+    pub fn swap(&self) -> &SwapCols<T> {
+        self.aux[..NUM_SWAP_COLS].borrow()
+    }
+
+    pub fn swap_mut(&mut self) -> &mut SwapCols<T> {
+        self.aux[..NUM_SWAP_COLS].borrow_mut()
+    }
+
+    pub fn add_rs(&self) -> &AddRsCols<T> {
+        self.aux[..NUM_ADD_RS_COLS].borrow()
+    }
+
+    pub fn add_rs_mut(&mut self) -> &mut AddRsCols<T> {
+        self.aux[..NUM_ADD_RS_COLS].borrow_mut()
+    }
+
+    pub fn cbranch(&self) -> &CBranchCols<T> {
+        self.aux[..NUM_CBRANCH_COLS].borrow()
+    }
+
+    pub fn cbranch_mut(&mut self) -> &mut CBranchCols<T> {
+        self.aux[..NUM_CBRANCH_COLS].borrow_mut()
+    }
+
+    // lane 0 holds the columns of the low 64-bit FP lane
+    pub fn fp_lane(&self, lane: usize) -> &FpLaneCols<T> {
+        self.aux[lane * NUM_FP_LANE_COLS..(lane + 1) * NUM_FP_LANE_COLS].borrow()
+    }
+
+    pub fn fp_lane_mut(&mut self, lane: usize) -> &mut FpLaneCols<T> {
+        self.aux[lane * NUM_FP_LANE_COLS..(lane + 1) * NUM_FP_LANE_COLS].borrow_mut()
+    }
+}
+
+impl<T> FpLaneCols<T> {
+    pub fn fadd(&self) -> &FAddCols<T> {
+        self.op[..].borrow()
+    }
+
+    pub fn fadd_mut(&mut self) -> &mut FAddCols<T> {
+        self.op[..].borrow_mut()
+    }
+
+    pub fn fmul(&self) -> &FMulCols<T> {
+        self.op[..NUM_FMUL_COLS].borrow()
+    }
+
+    pub fn fmul_mut(&mut self) -> &mut FMulCols<T> {
+        self.op[..NUM_FMUL_COLS].borrow_mut()
+    }
+
+    pub fn fdiv(&self) -> &FDivCols<T> {
+        self.op[..NUM_FDIV_COLS].borrow()
+    }
+
+    pub fn fdiv_mut(&mut self) -> &mut FDivCols<T> {
+        self.op[..NUM_FDIV_COLS].borrow_mut()
+    }
+
+    pub fn fsqrt(&self) -> &FSqrtCols<T> {
+        self.op[..NUM_FSQRT_COLS].borrow()
+    }
+
+    pub fn fsqrt_mut(&mut self) -> &mut FSqrtCols<T> {
+        self.op[..NUM_FSQRT_COLS].borrow_mut()
+    }
+}